@@ -58,3 +58,10 @@ pub struct BurnFromAction {
     pub amount: Uint128,
     pub memo: Option<String>,
 }
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct BatchBondAction {
+    pub recipient: HumanAddr,
+    pub amount: Uint128,
+}
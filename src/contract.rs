@@ -8,33 +8,41 @@ use cosmwasm_std::{
 use crate::distributors::{
     get_distributor, try_add_distributors, try_set_distributors, try_set_distributors_status,
 };
+use crate::errors::ContractError;
 use crate::expose_balance::{try_expose_balance, try_expose_balance_with_cooldown};
 use crate::msg::{
     space_pad, ContractStatusLevel, HandleAnswer, HandleMsg, InitMsg, QueryAnswer, QueryMsg,
-    ResponseStatus::Success,
+    ResponseStatus::Success, StakeReceiveType,
 };
 use crate::msg::{status_level_to_u8, QueryWithPermit};
 use crate::rand::sha_256;
 use crate::receiver::Snip20ReceiveMsg;
+use crate::reward_tokens::{accrue_rewards, try_add_reward_tokens};
 use crate::stake::{
-    claim_rewards, remove_from_cooldown, shares_per_token, try_claim_rewards, try_claim_unbond,
-    try_receive, try_stake_rewards, try_unbond, try_update_stake_config,
+    claim_rewards, locked_tokens, payout_implicit_claim, register_holder, remove_from_cooldown,
+    shares_per_token, try_cancel_unbond, try_claim_rewards, try_claim_rewards_for,
+    try_claim_unbond, try_claim_vested, try_instant_unbond, try_lock_stake, try_receive,
+    try_report_loss, try_revoke_operator, try_set_auto_compound, try_set_operator,
+    try_stake_rewards, try_stake_rewards_for, try_sweep_rounding_dust, try_unbond,
+    try_update_stake_config, Payout, DAY,
 };
 use crate::state::{
     get_receiver_hash, read_allowance, read_viewing_key, set_receiver_hash, write_allowance,
     write_viewing_key, Balances, Config, Constants, ReadonlyBalances, ReadonlyConfig,
 };
 use crate::state_staking::{
-    DailyUnbondingQueue, Distributors, DistributorsEnabled, TotalShares, TotalTokens,
-    TotalUnbonding, UnsentStakedTokens, UserCooldown, UserShares,
-};
-use crate::transaction_history::{
-    get_transfers, get_txs, store_claim_reward, store_mint, store_transfer,
+    BondLimits, Distributors, DistributorsEnabled, HeldRewards, HolderCount, InstantUnbondConfig,
+    LastRateChange, LastUnbondingRelease, LiquidityBuffer, LockTiers, RateSnapshotCount,
+    ReportedLossCount, RewardDrip, RewardDripPeriod, RewardTokens, RewardVestingPeriod,
+    RoundingDust, StakeFees, TotalLockBonus, TotalShares, TotalTokens, TotalUnbonding,
+    TreasuryCallback, UnbondingCount, UnbondingEpoch, UnbondingEpochs, UnfundedDays,
+    UnsentStakedTokens, UserCooldown, UserShares,
 };
+use crate::transaction_history::{get_transfers, get_txs, store_mint, store_transfer};
 use crate::viewing_key::{ViewingKey, VIEWING_KEY_SIZE};
-use crate::{batch, distributors, stake_queries};
+use crate::{batch, distributors, reward_tokens, stake_queries};
 use secret_toolkit::permit::{validate, Permission, Permit, RevokedPermits};
-use secret_toolkit::snip20::{register_receive_msg, send_msg, token_info_query};
+use secret_toolkit::snip20::{register_receive_msg, token_info_query};
 use shade_protocol::shd_staking::stake::{Cooldown, StakeConfig, VecQueue};
 use shade_protocol::shd_staking::ReceiveType;
 use shade_protocol::utils::storage::{BucketStorage, SingletonStorage};
@@ -89,6 +97,7 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         admin,
         prng_seed: prng_seed_hashed.to_vec(),
         total_supply_is_public: init_config.public_total_supply(),
+        balance_with_rewards: init_config.balance_with_rewards(),
         contract_address: env.contract.address,
     })?;
     config.set_total_supply(total_supply);
@@ -99,9 +108,10 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
     DistributorsEnabled(msg.limit_transfer).save(&mut deps.storage)?;
 
     if staked_token_decimals * 2 > msg.share_decimals {
-        return Err(StdError::generic_err(
+        return Err(ContractError::InvalidConfig(
             "Share decimals must be two times greater than the token decimals",
-        ));
+        )
+        .into());
     }
 
     StakeConfig {
@@ -115,8 +125,19 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
     // Set shares state to 0
     TotalShares(Uint128::zero()).save(&mut deps.storage)?;
 
-    // Initialize unbonding queue
-    DailyUnbondingQueue(VecQueue::new(vec![])).save(&mut deps.storage)?;
+    // Initialize unbonding ledger
+    UnfundedDays {
+        head: None,
+        tail: None,
+    }
+    .save(&mut deps.storage)?;
+    UnbondingEpochs(vec![UnbondingEpoch {
+        start: 0,
+        length: DAY,
+    }])
+    .save(&mut deps.storage)?;
+    LastUnbondingRelease(0).save(&mut deps.storage)?;
+    TreasuryCallback::default().save(&mut deps.storage)?;
 
     // Set tokens
     TotalTokens(Uint128::zero()).save(&mut deps.storage)?;
@@ -125,6 +146,51 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
 
     UnsentStakedTokens(Uint128::zero()).save(&mut deps.storage)?;
 
+    RewardTokens(vec![]).save(&mut deps.storage)?;
+
+    // Rewards are added instantly until a drip period is set
+    RewardDripPeriod(0).save(&mut deps.storage)?;
+    RewardDrip {
+        remaining: Uint128::zero(),
+        last_update: env.block.time,
+        end: env.block.time,
+    }
+    .save(&mut deps.storage)?;
+
+    RoundingDust {
+        tokens: Uint128::zero(),
+        remainder: Uint128::zero(),
+    }
+    .save(&mut deps.storage)?;
+
+    // No lock tiers until the admin sets them
+    LockTiers(vec![]).save(&mut deps.storage)?;
+    TotalLockBonus(Uint128::zero()).save(&mut deps.storage)?;
+
+    BondLimits {
+        min_bond: None,
+        max_bond_per_account: None,
+        max_total_staked: None,
+    }
+    .save(&mut deps.storage)?;
+    StakeFees { bond: 0, unbond: 0 }.save(&mut deps.storage)?;
+
+    // No tokens are kept back for instant unbonds until the admin sets a buffer
+    InstantUnbondConfig {
+        penalty: 0,
+        buffer: Uint128::zero(),
+    }
+    .save(&mut deps.storage)?;
+    LiquidityBuffer(Uint128::zero()).save(&mut deps.storage)?;
+    HeldRewards(Uint128::zero()).save(&mut deps.storage)?;
+
+    LastRateChange(env.block.time).save(&mut deps.storage)?;
+    RateSnapshotCount(0).save(&mut deps.storage)?;
+    RewardVestingPeriod(0).save(&mut deps.storage)?;
+    HolderCount(0).save(&mut deps.storage)?;
+    UnbondingCount(0).save(&mut deps.storage)?;
+    ReportedLossCount(0).save(&mut deps.storage)?;
+
     // Register receive if necessary
     let mut messages = vec![];
     if let Some(addr) = msg.treasury {
@@ -180,35 +246,46 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
                 // This is always allowed
                 HandleMsg::SetContractStatus { .. } => {}
                 HandleMsg::UpdateStakeConfig { .. } => {}
+                HandleMsg::ReportLoss { .. } => {}
 
                 // If receive check that msg is not bonding or reward
                 HandleMsg::Receive { msg, .. } => {
-                    let receive_type: ReceiveType;
-                    if let Some(msg) = msg {
-                        receive_type = from_binary(&msg)?;
-                    } else {
-                        return Err(StdError::generic_err("No receive type supplied in message"));
-                    }
-
-                    match receive_type {
-                        ReceiveType::Bond { .. } | ReceiveType::Reward => not_authorized = true,
-                        _ => {}
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => return Err(ContractError::NoReceiveType.into()),
+                    };
+
+                    match from_binary(&msg) {
+                        Ok(ReceiveType::Bond { .. }) | Ok(ReceiveType::Reward) => {
+                            not_authorized = true
+                        }
+                        Ok(_) => {}
+                        // Batch bonds and locked bonds are the other receive types
+                        Err(err) => {
+                            let _: StakeReceiveType = from_binary(&msg).map_err(|_| err)?;
+                            not_authorized = true;
+                        }
                     }
                 }
                 // Relates to bonding
-                HandleMsg::StakeRewards { .. } => {
+                HandleMsg::StakeRewards { .. }
+                | HandleMsg::StakeRewardsFor { .. }
+                | HandleMsg::LockStake { .. }
+                | HandleMsg::CancelUnbond { .. } => {
                     if status_code > 0 {
                         not_authorized = true;
                     }
                 }
 
-                HandleMsg::ClaimRewards { .. } => {
+                HandleMsg::ClaimRewards { .. }
+                | HandleMsg::ClaimRewardsFor { .. }
+                | HandleMsg::ClaimVested { .. } => {
                     if status_code > 1 {
                         not_authorized = true;
                     }
                 }
                 // If unbonding check that msg is not stop all
-                HandleMsg::Unbond { .. } => {
+                HandleMsg::Unbond { .. } | HandleMsg::InstantUnbond { .. } => {
                     if status_code > 2 {
                         not_authorized = true;
                     }
@@ -227,9 +304,7 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
             }
 
             if not_authorized {
-                return pad_response(Err(StdError::generic_err(
-                    "This contract is stopped and this action is not allowed",
-                )));
+                return pad_response(Err(ContractError::ContractStopped.into()));
             }
         }
     };
@@ -240,8 +315,30 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
             unbond_time,
             disable_treasury,
             treasury,
+            reward_drip_period,
+            lock_tiers,
+            bond_limits,
+            fees,
+            instant_unbond,
+            reward_vesting_period,
+            unbonding_epoch,
+            treasury_callback,
             ..
-        } => try_update_stake_config(deps, env, unbond_time, disable_treasury, treasury),
+        } => try_update_stake_config(
+            deps,
+            env,
+            unbond_time,
+            disable_treasury,
+            treasury,
+            reward_drip_period,
+            lock_tiers,
+            bond_limits,
+            fees,
+            instant_unbond,
+            reward_vesting_period,
+            unbonding_epoch,
+            treasury_callback,
+        ),
         HandleMsg::Receive {
             sender,
             from,
@@ -251,9 +348,54 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
             ..
         } => try_receive(deps, env, sender, from, amount, msg, memo),
         HandleMsg::Unbond { amount, .. } => try_unbond(deps, env, amount),
-        HandleMsg::ClaimUnbond { .. } => try_claim_unbond(deps, env),
-        HandleMsg::ClaimRewards { .. } => try_claim_rewards(deps, env),
+        HandleMsg::InstantUnbond { amount, .. } => try_instant_unbond(deps, env, amount),
+        HandleMsg::CancelUnbond { amount, .. } => try_cancel_unbond(deps, env, amount),
+        HandleMsg::ClaimUnbond {
+            ids,
+            amount,
+            recipient,
+            recipient_code_hash,
+            msg,
+            ..
+        } => {
+            let payout = Payout::new(&env.message.sender, recipient, recipient_code_hash, msg);
+            try_claim_unbond(deps, env, ids, amount, payout)
+        }
+        HandleMsg::ClaimRewards {
+            recipient,
+            recipient_code_hash,
+            msg,
+            ..
+        } => {
+            let payout = Payout::new(&env.message.sender, recipient, recipient_code_hash, msg);
+            try_claim_rewards(deps, env, payout)
+        }
+        HandleMsg::ClaimVested { .. } => try_claim_vested(deps, env),
         HandleMsg::StakeRewards { .. } => try_stake_rewards(deps, env),
+        HandleMsg::SetOperator {
+            operator,
+            max_tip,
+            expiration,
+            ..
+        } => try_set_operator(deps, env, operator, max_tip, expiration),
+        HandleMsg::RevokeOperator { operator, .. } => try_revoke_operator(deps, env, operator),
+        HandleMsg::ClaimRewardsFor { owner, tip, .. } => {
+            try_claim_rewards_for(deps, env, owner, tip)
+        }
+        HandleMsg::StakeRewardsFor { owner, tip, .. } => {
+            try_stake_rewards_for(deps, env, owner, tip)
+        }
+        HandleMsg::SetAutoCompound { enabled, .. } => try_set_auto_compound(deps, env, enabled),
+        HandleMsg::ReportLoss { amount, memo, .. } => try_report_loss(deps, env, amount, memo),
+        HandleMsg::LockStake {
+            amount, duration, ..
+        } => try_lock_stake(deps, env, amount, duration),
+        HandleMsg::SweepRoundingDust { to_treasury, .. } => {
+            try_sweep_rounding_dust(deps, env, to_treasury)
+        }
+
+        // Reward tokens
+        HandleMsg::AddRewardTokens { tokens, .. } => try_add_reward_tokens(deps, env, tokens),
 
         // Balance
         HandleMsg::ExposeBalance {
@@ -360,8 +502,25 @@ pub fn query<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, msg: QueryM
         QueryMsg::StakeConfig {} => stake_queries::stake_config(deps),
         QueryMsg::TotalStaked {} => stake_queries::total_staked(deps),
         QueryMsg::StakeRate {} => stake_queries::stake_rate(deps),
+        QueryMsg::ExchangeRate { time } => stake_queries::exchange_rate(deps, time),
+        QueryMsg::RewardDrip { time } => stake_queries::reward_drip(deps, time),
+        QueryMsg::RoundingDust {} => stake_queries::rounding_dust(deps),
+        QueryMsg::InstantLiquidity {} => stake_queries::instant_liquidity(deps),
+        QueryMsg::StakeRateHistory { page, page_size } => {
+            stake_queries::stake_rate_history(deps, page.unwrap_or(0), page_size)
+        }
+        QueryMsg::LossHistory { page, page_size } => {
+            stake_queries::loss_history(deps, page.unwrap_or(0), page_size)
+        }
+        QueryMsg::EstimatedApr { time, window } => stake_queries::estimated_apr(deps, time, window),
         QueryMsg::Unbonding {} => stake_queries::unbonding(deps),
         QueryMsg::Unfunded { start, total } => stake_queries::unfunded(deps, start, total),
+        QueryMsg::UnbondingSchedule {
+            start_after,
+            limit,
+            time,
+        } => stake_queries::unbonding_schedule(deps, start_after, limit, time),
+        QueryMsg::RewardTokens {} => reward_tokens::reward_tokens(deps),
         QueryMsg::Distributors {} => distributors::distributors(deps),
         QueryMsg::TokenInfo {} => query_token_info(&deps.storage),
         QueryMsg::TokenConfig {} => query_token_config(&deps.storage),
@@ -405,6 +564,16 @@ fn permit_queries<S: Storage, A: Api, Q: Querier>(
 
             query_balance(deps, &account)
         }
+        QueryWithPermit::BalanceWithRewards { time } => {
+            if !permit.check_permission(&Permission::Balance) {
+                return Err(StdError::generic_err(format!(
+                    "No permission to query balance, got permissions {:?}",
+                    permit.params.permissions
+                )));
+            }
+
+            stake_queries::balance_with_rewards(deps, &account, time)
+        }
         QueryWithPermit::TransferHistory { page, page_size } => {
             if !permit.check_permission(&Permission::History) {
                 return Err(StdError::generic_err(format!(
@@ -466,7 +635,20 @@ pub fn viewing_keys_queries<S: Storage, A: Api, Q: Querier>(
                 QueryMsg::Staked { address, time, .. } => {
                     stake_queries::staked(deps, address, time)
                 }
+                QueryMsg::Operator {
+                    owner, operator, ..
+                } => stake_queries::operator(deps, owner, operator),
+                QueryMsg::AuditInvariants {
+                    address,
+                    page,
+                    page_size,
+                    totals,
+                    ..
+                } => stake_queries::audit_invariants(deps, address, page, page_size, totals),
                 QueryMsg::Balance { address, .. } => query_balance(deps, &address),
+                QueryMsg::BalanceWithRewards { address, time, .. } => {
+                    stake_queries::balance_with_rewards(deps, &address, time)
+                }
                 QueryMsg::TransferHistory {
                     address,
                     page,
@@ -514,6 +696,7 @@ fn query_token_config<S: ReadonlyStorage>(storage: &S) -> QueryResult {
 
     to_binary(&QueryAnswer::TokenConfig {
         public_total_supply: constants.total_supply_is_public,
+        balance_with_rewards: constants.balance_with_rewards,
     })
 }
 
@@ -561,6 +744,13 @@ pub fn query_balance<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     account: &HumanAddr,
 ) -> StdResult<Binary> {
+    if ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .balance_with_rewards
+    {
+        return stake_queries::balance_with_rewards(deps, account, None);
+    }
+
     let address = deps.api.canonical_address(account)?;
 
     let amount = Uint128(ReadonlyBalances::from_storage(&deps.storage).account_amount(&address));
@@ -610,9 +800,7 @@ pub fn try_mint_impl<S: Storage>(
         // of the total supply, both are stored as u128, and we check for overflow of
         // the total supply just a couple lines before.
         // Still, writing this to cover all overflows.
-        return Err(StdError::generic_err(
-            "This mint attempt would increase the account's balance above the supported maximum",
-        ));
+        return Err(ContractError::BalanceOverflow.into());
     }
 
     balances.set_account_balance(recipient, account_balance);
@@ -711,7 +899,6 @@ fn try_transfer_impl<S: Storage, A: Api, Q: Querier>(
     block: &cosmwasm_std::BlockInfo,
 
     distributors: &Option<Vec<HumanAddr>>,
-    time: u64,
 ) -> StdResult<()> {
     // Verify that this transfer is allowed
     if let Some(distributors) = distributors {
@@ -720,42 +907,19 @@ fn try_transfer_impl<S: Storage, A: Api, Q: Querier>(
         }
     }
 
-    let symbol = Config::from_storage(&mut deps.storage).constants()?.symbol;
-
-    let stake_config = StakeConfig::load(&deps.storage)?;
-    let claim = claim_rewards(&mut deps.storage, &stake_config, sender, sender_canon)?;
-    if claim != 0 {
-        messages.push(send_msg(
-            sender.clone(),
-            Uint128(claim),
-            None,
-            None,
-            None,
-            256,
-            stake_config.staked_token.code_hash,
-            stake_config.staked_token.address,
-        )?);
-
-        store_claim_reward(
-            &mut deps.storage,
-            sender_canon,
-            Uint128(claim),
-            symbol.clone(),
-            None,
-            block,
-        )?;
-    }
-
     perform_transfer(
         &mut deps.storage,
+        messages,
         sender,
         sender_canon,
         recipient,
         recipient_canon,
         amount.u128(),
-        time,
+        block,
     )?;
 
+    let symbol = Config::from_storage(&mut deps.storage).constants()?.symbol;
+
     store_transfer(
         &mut deps.storage,
         sender_canon,
@@ -796,7 +960,6 @@ fn try_transfer<S: Storage, A: Api, Q: Querier>(
         memo,
         &env.block,
         &distributor,
-        env.block.time,
     )?;
 
     let res = HandleResponse {
@@ -833,7 +996,6 @@ fn try_batch_transfer<S: Storage, A: Api, Q: Querier>(
             action.memo,
             &env.block,
             &distributor,
-            env.block.time,
         )?;
     }
 
@@ -890,7 +1052,6 @@ fn try_send_impl<S: Storage, A: Api, Q: Querier>(
     block: &cosmwasm_std::BlockInfo,
 
     distributors: &Option<Vec<HumanAddr>>,
-    time: u64,
 ) -> StdResult<()> {
     let recipient_canon = deps.api.canonical_address(&recipient)?;
     try_transfer_impl(
@@ -904,7 +1065,6 @@ fn try_send_impl<S: Storage, A: Api, Q: Querier>(
         memo.clone(),
         block,
         distributors,
-        time,
     )?;
 
     try_add_receiver_api_callback(
@@ -949,7 +1109,6 @@ fn try_send<S: Storage, A: Api, Q: Querier>(
         msg,
         &env.block,
         &distributor,
-        env.block.time,
     )?;
 
     let res = HandleResponse {
@@ -984,7 +1143,6 @@ fn try_batch_send<S: Storage, A: Api, Q: Querier>(
             action.msg,
             &env.block,
             &distributor,
-            env.block.time,
         )?;
     }
 
@@ -1046,6 +1204,7 @@ fn use_allowance<S: Storage>(
 fn try_transfer_from_impl<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: &Env,
+    messages: &mut Vec<CosmosMsg>,
     spender: &HumanAddr,
     spender_canon: &CanonicalAddr,
     owner: &HumanAddr,
@@ -1056,7 +1215,6 @@ fn try_transfer_from_impl<S: Storage, A: Api, Q: Querier>(
     memo: Option<String>,
 
     distributors: &Option<Vec<HumanAddr>>,
-    time: u64,
 ) -> StdResult<()> {
     // Verify that this transfer is allowed
     if let Some(distributors) = distributors {
@@ -1080,12 +1238,13 @@ fn try_transfer_from_impl<S: Storage, A: Api, Q: Querier>(
 
     perform_transfer(
         &mut deps.storage,
+        messages,
         owner,
         owner_canon,
         recipient,
         recipient_canon,
         raw_amount,
-        time,
+        &env.block,
     )?;
 
     let symbol = Config::from_storage(&mut deps.storage).constants()?.symbol;
@@ -1116,9 +1275,13 @@ fn try_transfer_from<S: Storage, A: Api, Q: Querier>(
     let spender_canon = deps.api.canonical_address(spender)?;
     let owner_canon = deps.api.canonical_address(owner)?;
     let recipient_canon = deps.api.canonical_address(recipient)?;
+
+    let mut messages = vec![];
+
     try_transfer_from_impl(
         deps,
         env,
+        &mut messages,
         spender,
         &spender_canon,
         owner,
@@ -1128,11 +1291,10 @@ fn try_transfer_from<S: Storage, A: Api, Q: Querier>(
         amount,
         memo,
         &get_distributor(deps)?,
-        env.block.time,
     )?;
 
     let res = HandleResponse {
-        messages: vec![],
+        messages,
         log: vec![],
         data: Some(to_binary(&HandleAnswer::TransferFrom { status: Success })?),
     };
@@ -1149,12 +1311,15 @@ fn try_batch_transfer_from<S: Storage, A: Api, Q: Querier>(
 
     let distributor = get_distributor(deps)?;
 
+    let mut messages = vec![];
+
     for action in actions {
         let owner_canon = deps.api.canonical_address(&action.owner)?;
         let recipient_canon = deps.api.canonical_address(&action.recipient)?;
         try_transfer_from_impl(
             deps,
             env,
+            &mut messages,
             spender,
             &spender_canon,
             &action.owner,
@@ -1164,12 +1329,11 @@ fn try_batch_transfer_from<S: Storage, A: Api, Q: Querier>(
             action.amount,
             action.memo,
             &distributor,
-            env.block.time,
         )?;
     }
 
     let res = HandleResponse {
-        messages: vec![],
+        messages,
         log: vec![],
         data: Some(to_binary(&HandleAnswer::BatchTransferFrom {
             status: Success,
@@ -1199,6 +1363,7 @@ fn try_send_from_impl<S: Storage, A: Api, Q: Querier>(
     try_transfer_from_impl(
         deps,
         &env,
+        messages,
         spender,
         spender_canon,
         &owner,
@@ -1208,7 +1373,6 @@ fn try_send_from_impl<S: Storage, A: Api, Q: Querier>(
         amount,
         memo.clone(),
         distributors,
-        env.block.time,
     )?;
 
     try_add_receiver_api_callback(
@@ -1390,20 +1554,47 @@ fn try_decrease_allowance<S: Storage, A: Api, Q: Querier>(
     Ok(res)
 }
 
+///
+/// Moves tokens and their shares, the sender's rewards are claimed first so every
+/// transfer path prices shares after the reward drip
+///
+#[allow(clippy::too_many_arguments)]
 fn perform_transfer<T: Storage>(
     store: &mut T,
+    messages: &mut Vec<CosmosMsg>,
     from: &HumanAddr,
     from_canon: &CanonicalAddr,
     to: &HumanAddr,
     to_canon: &CanonicalAddr,
     amount: u128,
-    time: u64,
+    block: &cosmwasm_std::BlockInfo,
 ) -> StdResult<()> {
+    let time = block.time;
+
+    let stake_config = StakeConfig::load(store)?;
+    let claim = claim_rewards(store, &stake_config, from, from_canon, block)?;
+    let symbol = Config::from_storage(store).constants()?.symbol;
+    payout_implicit_claim(
+        store,
+        messages,
+        &stake_config,
+        from,
+        from_canon,
+        claim,
+        symbol,
+        block,
+    )?;
+
+    let locked = locked_tokens(store, from, time)?;
     let mut balances = Balances::from_storage(store);
 
     let mut from_balance = balances.balance(from_canon);
     let from_tokens = from_balance;
 
+    if amount > from_balance.saturating_sub(locked) {
+        return Err(ContractError::TokensLocked.into());
+    }
+
     if let Some(new_from_balance) = from_balance.checked_sub(amount) {
         from_balance = new_from_balance;
     } else {
@@ -1420,16 +1611,15 @@ fn perform_transfer<T: Storage>(
         StdError::generic_err("This tx will literally make them too rich. Try transferring less")
     })?;
     balances.set_account_balance(to_canon, to_balance);
+    register_holder(store, to)?;
 
     // Transfer shares
     let total_tokens = TotalTokens::load(store)?;
     let total_shares = TotalShares::load(store)?;
 
-    let config = StakeConfig::load(store)?;
-
     // calculate shares per token
     let transfer_shares = Uint128(shares_per_token(
-        &config,
+        &stake_config,
         &amount,
         &total_tokens.0.u128(),
         &total_shares.0.u128(),
@@ -1437,12 +1627,14 @@ fn perform_transfer<T: Storage>(
 
     // move shares from one user to another
     let mut from_shares = UserShares::load(store, from.as_str().as_bytes())?;
+    accrue_rewards(store, from, from_shares.0.u128())?;
 
     from_shares.0 = (from_shares.0 - transfer_shares)?;
     from_shares.save(store, from.as_str().as_bytes())?;
 
     let mut to_shares =
         UserShares::may_load(store, to.as_str().as_bytes())?.unwrap_or(UserShares(Uint128::zero()));
+    accrue_rewards(store, to, to_shares.0.u128())?;
     to_shares.0 += transfer_shares;
     to_shares.save(store, to.as_str().as_bytes())?;
 
@@ -1464,7 +1656,7 @@ fn perform_transfer<T: Storage>(
         // add the new cooldown
         to_cooldown.add_cooldown(Cooldown {
             amount: wrapped_amount,
-            release: time + StakeConfig::load(store)?.unbond_time,
+            release: time + stake_config.unbond_time,
         });
         to_cooldown.save(store, to.as_str().as_bytes())?;
     }
@@ -1502,9 +1694,7 @@ fn is_admin<S: Storage>(config: &Config<S>, account: &HumanAddr) -> StdResult<bo
 
 pub fn check_if_admin<S: Storage>(config: &Config<S>, account: &HumanAddr) -> StdResult<()> {
     if !is_admin(config, account)? {
-        return Err(StdError::generic_err(
-            "This is an admin command. Admin commands can only be run from admin address",
-        ));
+        return Err(ContractError::NotAdmin.into());
     }
 
     Ok(())
@@ -1533,10 +1723,17 @@ fn is_valid_symbol(symbol: &str) -> bool {
 #[cfg(test)]
 mod staking_tests {
     use super::*;
+    use crate::msg::AuditTotals;
     use crate::msg::InitConfig;
     use crate::msg::ResponseStatus;
+    use crate::msg::ScheduledUnbonding;
+    use crate::msg::UnbondingEntry;
+    use crate::stake::minimum_stake;
+    use crate::state_staking::{TreasuryCallback, UnbondingEpoch};
+    use crate::treasury::TreasuryHandleMsg;
     use cosmwasm_std::testing::*;
     use cosmwasm_std::{from_binary, BlockInfo, ContractInfo, MessageInfo, QueryResponse, WasmMsg};
+    use secret_toolkit::utils::HandleCallback;
     use shade_protocol::shd_staking::ReceiveType;
     use shade_protocol::utils::asset::Contract;
     use std::any::Any;
@@ -1579,6 +1776,14 @@ mod staking_tests {
             unbond_time: Some(100),
             disable_treasury: true,
             treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
             padding: None,
         };
         // Check that only admins can interact
@@ -1591,7 +1796,7 @@ mod staking_tests {
 
         let query_response = query(&deps, query_balance_msg).unwrap();
         let config = match from_binary(&query_response).unwrap() {
-            QueryAnswer::StakedConfig { config } => config,
+            QueryAnswer::StakedConfig { config, .. } => config,
             _ => panic!("Unexpected result from query"),
         };
 
@@ -1950,7 +2155,14 @@ mod staking_tests {
         };
 
         // Try to claim when its funded but the date hasn't been reached
-        let handle_msg = HandleMsg::ClaimUnbond { padding: None };
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
         let mut env = mock_env("foo", &[]);
         env.block.time = 0;
         let handle_result = handle(&mut deps, env, handle_msg.clone());
@@ -1983,7 +2195,14 @@ mod staking_tests {
         };
 
         // Claim
-        let handle_msg = HandleMsg::ClaimUnbond { padding: None };
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
         let mut env = mock_env("foo", &[]);
         env.block.time = 11;
         let handle_result = handle(&mut deps, env, handle_msg.clone());
@@ -2027,7 +2246,14 @@ mod staking_tests {
         assert!(handle_result.is_ok());
 
         // Claim
-        let handle_msg = HandleMsg::ClaimUnbond { padding: None };
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
         let mut env = mock_env("foo", &[]);
         env.block.time = 11;
         let handle_result = handle(&mut deps, env, handle_msg.clone());
@@ -2043,7 +2269,12 @@ mod staking_tests {
         new_staked_account(&mut deps, "bar", "key", Uint128(50 * 10u128.pow(8)));
 
         // Claim rewards
-        let handle_msg = HandleMsg::ClaimRewards { padding: None };
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
 
         let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg.clone());
         assert!(handle_result.is_err());
@@ -2120,7 +2351,12 @@ mod staking_tests {
         );
 
         // Claim rewards
-        let handle_msg = HandleMsg::ClaimRewards { padding: None };
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
 
         let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg.clone());
         assert!(handle_result.is_ok());
@@ -2338,6 +2574,3272 @@ mod staking_tests {
         };
     }
 
+    #[test]
+    fn test_handle_reward_drip() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: Some(100),
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        // Add rewards
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(50 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let env = mock_env("token", &[]);
+        let start = env.block.time;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Nothing is released right away
+        check_staked_state(
+            &deps,
+            Uint128(100 * 10u128.pow(8)),
+            Uint128(100 * 10u128.pow(18)),
+        );
+
+        // Half way through the drip
+        let query_msg = QueryMsg::RewardDrip {
+            time: Some(start + 50),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::RewardDrip {
+                remaining,
+                rate,
+                end,
+            } => {
+                assert_eq!(remaining, Uint128(25 * 10u128.pow(8)));
+                assert_eq!(rate, Uint128(5 * 10u128.pow(7)));
+                assert_eq!(end, start + 100);
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Queries include what was released by the given time
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: Some(start + 50),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                pending_rewards, ..
+            } => {
+                assert_eq!(pending_rewards, Uint128(25 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::BalanceWithRewards {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: Some(start + 50),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Balance { amount } => {
+                assert_eq!(amount, Uint128(125 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::ExchangeRate {
+            time: Some(start + 50),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::ExchangeRate {
+                rate, last_update, ..
+            } => {
+                assert_eq!(rate, Uint128(125 * 10u128.pow(16)));
+                assert_eq!(last_update, start + 50);
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Claim what has been released so far
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let mut env = mock_env("foo", &[]);
+        env.block.time = start + 50;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_msg = QueryMsg::RewardDrip { time: None };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::RewardDrip { remaining, .. } => {
+                assert_eq!(remaining, Uint128(25 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::TotalStaked {};
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TotalStaked { tokens, .. } => {
+                assert_eq!(tokens, Uint128(100 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_transfer_from_reward_drip() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: Some(100),
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(50 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let env = mock_env("token", &[]);
+        let start = env.block.time;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::IncreaseAllowance {
+            spender: HumanAddr("bar".to_string()),
+            amount: Uint128(50 * 10u128.pow(8)),
+            padding: None,
+            expiration: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::SetViewingKey {
+            key: "key".to_string(),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("baz", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // The owner's dripped rewards are paid out before the shares are priced
+        let handle_msg = HandleMsg::TransferFrom {
+            owner: HumanAddr("foo".to_string()),
+            recipient: HumanAddr("baz".to_string()),
+            amount: Uint128(50 * 10u128.pow(8)),
+            memo: None,
+            padding: None,
+        };
+        let mut env = mock_env("bar", &[]);
+        env.block.time = start + 50;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        check_staked_state(
+            &deps,
+            Uint128(100 * 10u128.pow(8)),
+            Uint128(80 * 10u128.pow(18)),
+        );
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("baz".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                tokens,
+                shares,
+                pending_rewards,
+                ..
+            } => {
+                assert_eq!(tokens, Uint128(50 * 10u128.pow(8)));
+                assert_eq!(shares, Uint128(40 * 10u128.pow(18)));
+                assert_eq!(pending_rewards, Uint128::zero());
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_reward_tokens() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        // Foo should get 2x more rewards than bar
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "bar", "key", Uint128(50 * 10u128.pow(8)));
+
+        let reward_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(30 * 10u128.pow(6)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+
+        // Rewards from tokens that are not whitelisted are rejected
+        let handle_result = handle(&mut deps, mock_env("reward", &[]), reward_msg.clone());
+        assert!(handle_result.is_err());
+
+        let handle_msg = HandleMsg::AddRewardTokens {
+            tokens: vec![Contract {
+                address: HumanAddr("reward".to_string()),
+                code_hash: "hash".to_string(),
+            }],
+            padding: None,
+        };
+        // Check that only admins can interact
+        let handle_result = handle(&mut deps, mock_env("not_admin", &[]), handle_msg.clone());
+        assert!(handle_result.is_err());
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Add rewards; foo should get 20 tkn and bar 10
+        let handle_result = handle(&mut deps, mock_env("reward", &[]), reward_msg);
+        assert!(handle_result.is_ok());
+
+        // Staked token rewards are untouched
+        check_staked_state(
+            &deps,
+            Uint128(150 * 10u128.pow(8)),
+            Uint128(150 * 10u128.pow(18)),
+        );
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+
+        let query_response = query(&deps, query_msg.clone()).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                pending_rewards,
+                reward_tokens,
+                ..
+            } => {
+                assert_eq!(pending_rewards, Uint128::zero());
+                assert_eq!(reward_tokens[0].token, HumanAddr("reward".to_string()));
+                assert_eq!(reward_tokens[0].amount, Uint128(20 * 10u128.pow(6)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_response = query(
+            &deps,
+            QueryMsg::Staked {
+                address: HumanAddr("bar".to_string()),
+                key: "key".to_string(),
+                time: None,
+            },
+        )
+        .unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked { reward_tokens, .. } => {
+                assert_eq!(reward_tokens[0].amount, Uint128(10 * 10u128.pow(6)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Claim rewards
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked { reward_tokens, .. } => {
+                assert_eq!(reward_tokens[0].amount, Uint128::zero());
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_auto_compound() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        // Add rewards
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(50 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::SetAutoCompound {
+            enabled: true,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Unbonding restakes the claimed rewards instead of sending them
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        let messages = handle_result.unwrap().messages;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0],
+            send_msg(
+                HumanAddr("treasury".to_string()),
+                Uint128(50 * 10u128.pow(8)),
+                None,
+                None,
+                None,
+                256,
+                "hash".to_string(),
+                HumanAddr("token".to_string()),
+            )
+            .unwrap()
+        );
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                tokens,
+                pending_rewards,
+                unbonding,
+                auto_compound,
+                ..
+            } => {
+                assert_eq!(tokens, Uint128(140 * 10u128.pow(8)));
+                assert_eq!(pending_rewards, Uint128::zero());
+                assert_eq!(unbonding, Uint128(10 * 10u128.pow(8)));
+                assert!(auto_compound);
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::TransactionHistory {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            page: None,
+            page_size: 10,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TransactionHistory { txs, .. } => {
+                assert_eq!(
+                    txs[1].action,
+                    crate::transaction_history::TxAction::Compound {
+                        staker: HumanAddr("foo".to_string()),
+                    }
+                );
+                assert_eq!(txs[1].coins.amount, Uint128(50 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_report_loss() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(20 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::ReportLoss {
+            amount: Uint128(12 * 10u128.pow(8)),
+            memo: None,
+            padding: None,
+        };
+        // Only the admin or the treasury can report losses
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg.clone());
+        assert!(handle_result.is_err());
+        let handle_result = handle(&mut deps, mock_env("treasury", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Loss is split between the bonded and the unbonding tokens
+        check_staked_state(
+            &deps,
+            Uint128(704 * 10u128.pow(7)),
+            Uint128(80 * 10u128.pow(18)),
+        );
+
+        let query_response = query(&deps, QueryMsg::Unbonding {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Unbonding { total } => {
+                assert_eq!(total, Uint128(176 * 10u128.pow(7)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                tokens,
+                pending_rewards,
+                unbonding,
+                ..
+            } => {
+                assert_eq!(tokens, Uint128(704 * 10u128.pow(7)));
+                assert_eq!(pending_rewards, Uint128::zero());
+                assert_eq!(unbonding, Uint128(176 * 10u128.pow(7)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::TransactionHistory {
+            address: HumanAddr("treasury".to_string()),
+            key: "key".to_string(),
+            page: None,
+            page_size: 10,
+        };
+        let handle_msg = HandleMsg::SetViewingKey {
+            key: "key".to_string(),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("treasury", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TransactionHistory { txs, .. } => {
+                assert_eq!(
+                    txs[0].action,
+                    crate::transaction_history::TxAction::ReportLoss {
+                        reporter: HumanAddr("treasury".to_string()),
+                    }
+                );
+                assert_eq!(txs[0].coins.amount, Uint128(12 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Stakers can see the loss without access to the reporter's history
+        let query_msg = QueryMsg::LossHistory {
+            page: None,
+            page_size: 10,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::LossHistory { losses, total } => {
+                assert_eq!(total, 1);
+                assert_eq!(losses[0].amount, Uint128(12 * 10u128.pow(8)));
+                assert_eq!(losses[0].staked, Uint128(96 * 10u128.pow(7)));
+                assert_eq!(losses[0].unbonding, Uint128(24 * 10u128.pow(7)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // The rest of the stake can still be unbonded after the loss is realized
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(704 * 10u128.pow(7)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+    }
+
+    #[test]
+    fn test_handle_report_loss_buffer() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: Some(InstantUnbondConfig {
+                penalty: 1000,
+                buffer: Uint128(50 * 10u128.pow(8)),
+            }),
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Half of the bond is kept back in the liquidity buffer
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(20 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // The treasury only holds 30 bonded and 20 unbonding tokens
+        let handle_msg = HandleMsg::ReportLoss {
+            amount: Uint128(50 * 10u128.pow(8)),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("treasury", &[]), handle_msg);
+        assert!(handle_result.is_err());
+
+        let handle_msg = HandleMsg::ReportLoss {
+            amount: Uint128(10 * 10u128.pow(8)),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("treasury", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // The loss is split over what the treasury holds
+        check_staked_state(
+            &deps,
+            Uint128(74 * 10u128.pow(8)),
+            Uint128(80 * 10u128.pow(18)),
+        );
+
+        let query_response = query(&deps, QueryMsg::Unbonding {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Unbonding { total } => {
+                assert_eq!(total, Uint128(16 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_report_loss_rewards() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        // Rewards stay in the contract to pay out claims
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(20 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // The treasury only holds the 100 bonded tokens
+        let handle_msg = HandleMsg::ReportLoss {
+            amount: Uint128(100 * 10u128.pow(8)),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("treasury", &[]), handle_msg);
+        match handle_result {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.contains("\"code\":52")),
+            _ => panic!("Expected an error"),
+        }
+
+        let handle_msg = HandleMsg::ReportLoss {
+            amount: Uint128(10 * 10u128.pow(8)),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("treasury", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+        check_staked_state(
+            &deps,
+            Uint128(110 * 10u128.pow(8)),
+            Uint128(100 * 10u128.pow(18)),
+        );
+
+        // The rewards left after the claim back the written down stake
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+        assert_eq!(
+            HeldRewards::load(&deps.storage).unwrap().0,
+            Uint128(10 * 10u128.pow(8))
+        );
+    }
+
+    #[test]
+    fn test_handle_lock_stake() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: Some(vec![crate::state_staking::LockTier {
+                duration: 100,
+                bonus: 5000,
+            }]),
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "bar", "key", Uint128(100 * 10u128.pow(8)));
+
+        // Only configured durations can be used
+        let handle_msg = HandleMsg::LockStake {
+            amount: Uint128(100 * 10u128.pow(8)),
+            duration: 50,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_err());
+
+        let handle_msg = HandleMsg::LockStake {
+            amount: Uint128(100 * 10u128.pow(8)),
+            duration: 100,
+            padding: None,
+        };
+        let env = mock_env("foo", &[]);
+        let start = env.block.time;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Add rewards
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(25 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Locked stake earns more than the same unlocked stake
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                tokens,
+                pending_rewards,
+                locks,
+                ..
+            } => {
+                assert_eq!(tokens, Uint128(100 * 10u128.pow(8)));
+                assert_eq!(pending_rewards, Uint128(15 * 10u128.pow(8)));
+                assert_eq!(
+                    locks,
+                    vec![crate::state_staking::Lock {
+                        amount: Uint128(100 * 10u128.pow(8)),
+                        bonus: Uint128(50 * 10u128.pow(8)),
+                        release: start + 100,
+                    }]
+                );
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("bar".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                pending_rewards, ..
+            } => {
+                assert_eq!(pending_rewards, Uint128(10 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Cannot unbond until the lock expires
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_err());
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(100 * 10u128.pow(8)),
+            padding: None,
+        };
+        let mut env = mock_env("foo", &[]);
+        env.block.time = start + 100;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked { tokens, locks, .. } => {
+                assert_eq!(tokens, Uint128::zero());
+                assert!(locks.is_empty());
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_bond_and_lock() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: Some(vec![crate::state_staking::LockTier {
+                duration: 100,
+                bonus: 5000,
+            }]),
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "bar", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("foo".to_string()),
+            from: Default::default(),
+            amount: Uint128(100 * 10u128.pow(8)),
+            msg: Some(
+                to_binary(&StakeReceiveType::BondAndLock {
+                    use_from: None,
+                    duration: 100,
+                })
+                .unwrap(),
+            ),
+            memo: None,
+            padding: None,
+        };
+        let env = mock_env("token", &[]);
+        let start = env.block.time;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::SetViewingKey {
+            key: "key".to_string(),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        check_staked_state(
+            &deps,
+            Uint128(250 * 10u128.pow(8)),
+            Uint128(250 * 10u128.pow(18)),
+        );
+
+        // Leaves less than the locked bonus is worth
+        let handle_msg = HandleMsg::ReportLoss {
+            amount: Uint128(199 * 10u128.pow(8)),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("treasury", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Expired locks are shown as released
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: Some(start + 100),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                tokens,
+                shares,
+                locks,
+                ..
+            } => {
+                assert_eq!(tokens, Uint128::zero());
+                assert_eq!(shares, Uint128::zero());
+                assert!(locks.is_empty());
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Releasing the lock removes the whole bonus
+        let handle_msg = HandleMsg::Transfer {
+            recipient: HumanAddr("bar".to_string()),
+            amount: Uint128::zero(),
+            memo: None,
+            padding: None,
+        };
+        let mut env = mock_env("foo", &[]);
+        env.block.time = start + 100;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        check_staked_state(&deps, Uint128(10u128.pow(8)), Uint128(100 * 10u128.pow(18)));
+        assert_eq!(
+            crate::state_staking::TotalLockBonus::load(&deps.storage)
+                .unwrap()
+                .0,
+            Uint128::zero()
+        );
+        assert_eq!(LastRateChange::load(&deps.storage).unwrap().0, start + 100);
+
+        let query_msg = QueryMsg::TransactionHistory {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            page: None,
+            page_size: 10,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TransactionHistory { txs, .. } => {
+                let lock = crate::transaction_history::TxAction::Lock {
+                    staker: HumanAddr("foo".to_string()),
+                };
+                let unlock = crate::transaction_history::TxAction::Unlock {
+                    staker: HumanAddr("foo".to_string()),
+                };
+                assert!(
+                    txs.iter()
+                        .any(|tx| tx.action == lock
+                            && tx.coins.amount == Uint128(100 * 10u128.pow(8)))
+                );
+                assert!(txs.iter().any(
+                    |tx| tx.action == unlock && tx.coins.amount == Uint128(100 * 10u128.pow(8))
+                ));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_rounding_dust() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "bar", "key", Uint128(200 * 10u128.pow(8)));
+
+        // Rewards that cannot be split evenly
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_response = query(&deps, QueryMsg::RoundingDust {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::RoundingDust { tokens, remainder } => {
+                assert_eq!(tokens, Uint128::zero());
+                assert_eq!(remainder, Uint128(333_333_333_311_111_110));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let handle_msg = HandleMsg::SweepRoundingDust {
+            to_treasury: true,
+            padding: None,
+        };
+        // Only admins can sweep
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg.clone());
+        assert!(handle_result.is_err());
+        // Nothing to sweep until a whole token is collected
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_err());
+    }
+
+    #[test]
+    fn test_handle_rounding_dust_repeated_claims() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "bar", "key", Uint128(200 * 10u128.pow(8)));
+
+        let reward_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let claim_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        for _ in 0..30 {
+            let handle_result = handle(&mut deps, mock_env("token", &[]), reward_msg.clone());
+            assert!(handle_result.is_ok());
+            let handle_result = handle(&mut deps, mock_env("foo", &[]), claim_msg.clone());
+            assert!(handle_result.is_ok());
+        }
+
+        // The claimed fractions are only taken out once so bar keeps its full share
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("bar".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                pending_rewards, ..
+            } => {
+                assert_eq!(pending_rewards, Uint128(200));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // And the rate is at least what it would be without any claims
+        let query_response = query(&deps, QueryMsg::ExchangeRate { time: None }).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::ExchangeRate { rate, .. } => {
+                assert!(rate >= Uint128(1_000_000_010_000_000_000));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_reward_empty_pool() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(100 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+
+        // Rewards can't be used to inflate the price of the first shares
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg.clone());
+        assert!(handle_result.is_err());
+
+        let error_msg = |result: StdResult<HandleResponse>| match result {
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            _ => panic!("Expected an error"),
+        };
+
+        // Neither can a pool of a few tokens
+        let bond_msg = |amount: u128| HandleMsg::Receive {
+            sender: HumanAddr("foo".to_string()),
+            from: Default::default(),
+            amount: Uint128(amount),
+            msg: Some(to_binary(&ReceiveType::Bond { useFrom: None }).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), bond_msg(1));
+        assert!(error_msg(handle_result).contains("\"code\":33"));
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(1000));
+        check_staked_state(&deps, Uint128(1000), Uint128(1000 * 10u128.pow(10)));
+
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // The pool can't be drained back down to a few tokens
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(999),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(error_msg(handle_result).contains("\"code\":33"));
+
+        // The next staker loses at most a rounding unit of the inflated price
+        new_staked_account(&mut deps, "bar", "key", Uint128(100 * 10u128.pow(8)));
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("bar".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                tokens,
+                shares,
+                pending_rewards,
+                ..
+            } => {
+                assert_eq!(tokens, Uint128(100 * 10u128.pow(8) - 1));
+                assert_eq!(shares, Uint128(9_999_999_000_000));
+                assert_eq!(pending_rewards, Uint128::zero());
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_inflation_attack_simulation() {
+        use rand::Rng;
+
+        let mut rand = rand::thread_rng();
+
+        let receive_msg = |sender: &str, amount: u128, msg: ReceiveType| HandleMsg::Receive {
+            sender: HumanAddr(sender.to_string()),
+            from: Default::default(),
+            amount: Uint128(amount),
+            msg: Some(to_binary(&msg).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let error_msg = |result: StdResult<HandleResponse>| match result {
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            _ => panic!("Expected an error"),
+        };
+        let staked_tokens = |deps: &Extern<MockStorage, MockApi, MockQuerier>, acc: &str| {
+            let query_msg = QueryMsg::Staked {
+                address: HumanAddr(acc.to_string()),
+                key: "key".to_string(),
+                time: None,
+            };
+            match from_binary(&query(deps, query_msg).unwrap()).unwrap() {
+                QueryAnswer::Staked { tokens, .. } => tokens.u128(),
+                _ => panic!("Unexpected result from query"),
+            }
+        };
+
+        for _ in 0..10 {
+            let (init_result, mut deps) = init_helper_staking();
+            assert!(init_result.is_ok());
+
+            // The attacker can't open the pool with a single token
+            let handle_result = handle(
+                &mut deps,
+                mock_env("token", &[]),
+                receive_msg("attacker", 1, ReceiveType::Bond { useFrom: None }),
+            );
+            assert!(error_msg(handle_result).contains("\"code\":33"));
+
+            // Nor donate into the empty pool
+            let handle_result = handle(
+                &mut deps,
+                mock_env("token", &[]),
+                receive_msg("treasury", 10u128.pow(8), ReceiveType::Reward),
+            );
+            assert!(error_msg(handle_result).contains("\"code\":32"));
+
+            // So the attack opens the pool with the minimum and donates after
+            let minimum = minimum_stake(8);
+            new_staked_account(&mut deps, "attacker", "key", Uint128(minimum));
+            let donation = rand.gen_range(1..10_000 * 10u128.pow(8));
+            let handle_result = handle(
+                &mut deps,
+                mock_env("token", &[]),
+                receive_msg("treasury", donation, ReceiveType::Reward),
+            );
+            assert!(handle_result.is_ok());
+
+            let mut victims = vec![];
+            for i in 0..rand.gen_range(1..=10) {
+                let victim = format!("victim{}", i);
+                let tokens = rand.gen_range(minimum..100 * 10u128.pow(8));
+                new_staked_account(&mut deps, &victim, "key", Uint128(tokens));
+                victims.push((victim, tokens));
+            }
+
+            // Each victim loses at most a rounding unit
+            let mut stolen = 0;
+            for (victim, tokens) in victims.iter() {
+                let value = staked_tokens(&deps, victim);
+                assert!(value + 1 >= *tokens);
+                stolen += tokens.saturating_sub(value);
+            }
+
+            let attacker_value = staked_tokens(&deps, "attacker");
+            assert!(attacker_value <= minimum + donation + stolen);
+        }
+    }
+
+    #[test]
+    fn test_handle_bond_after_empty_pool() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: Some(100),
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(100 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let env = mock_env("token", &[]);
+        let start = env.block.time;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Leaves while half of the reward is still dripping
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(100 * 10u128.pow(8)),
+            padding: None,
+        };
+        let mut env = mock_env("foo", &[]);
+        env.block.time = start + 50;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+        check_staked_state(&deps, Uint128::zero(), Uint128::zero());
+
+        // The next staker doesn't inherit what the empty pool still held
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("bar".to_string()),
+            from: Default::default(),
+            amount: Uint128(100 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Bond { useFrom: None }).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let mut env = mock_env("token", &[]);
+        env.block.time = start + 100;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+        check_staked_state(
+            &deps,
+            Uint128(100 * 10u128.pow(8)),
+            Uint128(100 * 10u128.pow(18)),
+        );
+
+        let query_response = query(&deps, QueryMsg::RoundingDust {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::RoundingDust { tokens, .. } => {
+                assert_eq!(tokens, Uint128(50 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_bond_limits() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: Some(BondLimits {
+                min_bond: Some(Uint128(10 * 10u128.pow(8))),
+                max_bond_per_account: Some(Uint128(100 * 10u128.pow(8))),
+                max_total_staked: Some(Uint128(150 * 10u128.pow(8))),
+            }),
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let bond_msg = |acc: &str, amount: u128| HandleMsg::Receive {
+            sender: HumanAddr(acc.to_string()),
+            from: Default::default(),
+            amount: Uint128(amount),
+            msg: Some(to_binary(&ReceiveType::Bond { useFrom: None }).unwrap()),
+            memo: None,
+            padding: None,
+        };
+
+        // Too small to bond
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            bond_msg("foo", 5 * 10u128.pow(8)),
+        );
+        assert!(handle_result.is_err());
+
+        // Over the account limit, 20 tokens are sent back
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            bond_msg("foo", 120 * 10u128.pow(8)),
+        );
+        assert_eq!(handle_result.unwrap().messages.len(), 2);
+        check_staked_state(
+            &deps,
+            Uint128(100 * 10u128.pow(8)),
+            Uint128(100 * 10u128.pow(18)),
+        );
+
+        // Over the total limit, 50 tokens are sent back
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            bond_msg("bar", 100 * 10u128.pow(8)),
+        );
+        assert_eq!(handle_result.unwrap().messages.len(), 2);
+        check_staked_state(
+            &deps,
+            Uint128(150 * 10u128.pow(8)),
+            Uint128(150 * 10u128.pow(18)),
+        );
+
+        // Everything is sent back once full
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            bond_msg("bar", 20 * 10u128.pow(8)),
+        );
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+        check_staked_state(
+            &deps,
+            Uint128(150 * 10u128.pow(8)),
+            Uint128(150 * 10u128.pow(18)),
+        );
+
+        let query_response = query(&deps, QueryMsg::StakeConfig {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::StakedConfig { bond_limits, .. } => {
+                assert_eq!(
+                    bond_limits.max_total_staked,
+                    Some(Uint128(150 * 10u128.pow(8)))
+                );
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_bond_limits_restaking() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let error_msg = |result: StdResult<HandleResponse>| match result {
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            _ => panic!("Expected an error"),
+        };
+
+        let config_msg = |min_bond: u128| HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: Some(BondLimits {
+                min_bond: Some(Uint128(min_bond)),
+                max_bond_per_account: Some(Uint128(100 * 10u128.pow(8))),
+                max_total_staked: Some(Uint128(150 * 10u128.pow(8))),
+            }),
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+
+        // A minimum over the caps would make bonding impossible
+        let handle_result = handle(
+            &mut deps,
+            mock_env("admin", &[]),
+            config_msg(120 * 10u128.pow(8)),
+        );
+        assert!(error_msg(handle_result).contains("\"code\":50"));
+
+        let handle_result = handle(
+            &mut deps,
+            mock_env("admin", &[]),
+            config_msg(10 * 10u128.pow(8)),
+        );
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "bar", "key", Uint128(50 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(20 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bar", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "baz", "key", Uint128(20 * 10u128.pow(8)));
+
+        // Cancelling can't bond past the total limit
+        let cancel_msg = HandleMsg::CancelUnbond {
+            amount: Uint128(20 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bar", &[]), cancel_msg.clone());
+        assert!(error_msg(handle_result).contains("\"code\":34"));
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(20 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("baz", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_result = handle(&mut deps, mock_env("bar", &[]), cancel_msg);
+        assert!(handle_result.is_ok());
+        check_staked_state(
+            &deps,
+            Uint128(150 * 10u128.pow(8)),
+            Uint128(150 * 10u128.pow(18)),
+        );
+
+        // Add rewards, 10 tokens for foo and 5 for bar
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(15 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Compounding over the limits pays the rewards out instead
+        let handle_msg = HandleMsg::SetAutoCompound {
+            enabled: true,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Restaking less than the minimum pays the rewards out too
+        let handle_msg = HandleMsg::StakeRewards { padding: None };
+        let handle_result = handle(&mut deps, mock_env("bar", &[]), handle_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        for (account, bonded) in [("foo", 90), ("bar", 50)].iter() {
+            let query_msg = QueryMsg::Staked {
+                address: HumanAddr(account.to_string()),
+                key: "key".to_string(),
+                time: None,
+            };
+            let query_response = query(&deps, query_msg).unwrap();
+            match from_binary(&query_response).unwrap() {
+                QueryAnswer::Staked {
+                    tokens,
+                    pending_rewards,
+                    ..
+                } => {
+                    assert_eq!(tokens, Uint128(bonded * 10u128.pow(8)));
+                    assert_eq!(pending_rewards, Uint128::zero());
+                }
+                _ => panic!("Unexpected result from query"),
+            };
+        }
+
+        let query_msg = QueryMsg::TransactionHistory {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            page: None,
+            page_size: 10,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TransactionHistory { txs, .. } => {
+                assert_eq!(
+                    txs[1].action,
+                    crate::transaction_history::TxAction::ClaimReward {
+                        staker: HumanAddr("foo".to_string()),
+                        recipient: None,
+                    }
+                );
+                assert_eq!(txs[1].coins.amount, Uint128(10 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_fees() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let mut handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: Some(StakeFees {
+                bond: 10001,
+                unbond: 200,
+            }),
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        // Fees can't be over 100%
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg.clone());
+        assert!(handle_result.is_err());
+
+        if let HandleMsg::UpdateStakeConfig { fees, .. } = &mut handle_msg {
+            *fees = Some(StakeFees {
+                bond: 100,
+                unbond: 200,
+            });
+        }
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // 1 token is kept as a fee
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        check_staked_state(
+            &deps,
+            Uint128(99 * 10u128.pow(8)),
+            Uint128(99 * 10u128.pow(18)),
+        );
+
+        // 1 token is kept as a fee
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(50 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+        check_staked_state(
+            &deps,
+            Uint128(49 * 10u128.pow(8)),
+            Uint128(49 * 10u128.pow(18)),
+        );
+
+        let query_response = query(&deps, QueryMsg::Unbonding {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Unbonding { total } => {
+                assert_eq!(total, Uint128(49 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::TransactionHistory {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            page: None,
+            page_size: 10,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TransactionHistory { txs, .. } => {
+                let fee = crate::transaction_history::TxAction::Fee {
+                    payer: HumanAddr("foo".to_string()),
+                };
+                assert_eq!(txs[1].action, fee);
+                assert_eq!(txs[1].coins.amount, Uint128(10u128.pow(8)));
+                assert_eq!(txs[2].action, fee);
+                assert_eq!(txs[2].coins.amount, Uint128(10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_instant_unbond() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: Some(InstantUnbondConfig {
+                penalty: 1000,
+                buffer: Uint128(50 * 10u128.pow(8)),
+            }),
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Only the first 50 tokens are kept back
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "bar", "key", Uint128(100 * 10u128.pow(8)));
+
+        let query_response = query(&deps, QueryMsg::InstantLiquidity {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::InstantLiquidity { amount } => {
+                assert_eq!(amount, Uint128(50 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Not enough liquidity for 90 tokens
+        let handle_msg = HandleMsg::InstantUnbond {
+            amount: Uint128(100 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_err());
+
+        // Foo gets 45 tokens and 5 are left for bar
+        let handle_msg = HandleMsg::InstantUnbond {
+            amount: Uint128(50 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        check_staked_state(
+            &deps,
+            Uint128(155 * 10u128.pow(8)),
+            Uint128(150 * 10u128.pow(18)),
+        );
+
+        let query_response = query(&deps, QueryMsg::InstantLiquidity {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::InstantLiquidity { amount } => {
+                assert_eq!(amount, Uint128(5 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::Unbonding {};
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Unbonding { total } => {
+                assert_eq!(total, Uint128::zero());
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_stake_rate_history() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+
+        // A reward on each of two new days
+        let first_day = 1_600_041_600;
+        let mut env = mock_env("token", &[]);
+        env.block.time = first_day;
+        let handle_result = handle(&mut deps, env, handle_msg.clone());
+        assert!(handle_result.is_ok());
+
+        let mut env = mock_env("token", &[]);
+        env.block.time = first_day + 86400;
+        let handle_result = handle(&mut deps, env, handle_msg.clone());
+        assert!(handle_result.is_ok());
+
+        // Same day so no new snapshot
+        let mut env = mock_env("token", &[]);
+        env.block.time = first_day + 86400 + 100;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_msg = QueryMsg::StakeRateHistory {
+            page: None,
+            page_size: 2,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::StakeRateHistory { snapshots, total } => {
+                assert_eq!(total, 3);
+                assert_eq!(snapshots.len(), 2);
+                assert_eq!(snapshots[0].day, first_day + 86400);
+                assert_eq!(snapshots[0].total_tokens, Uint128(110 * 10u128.pow(8)));
+                assert_eq!(snapshots[1].day, first_day);
+                assert_eq!(snapshots[1].total_tokens, Uint128(100 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Window starts halfway through the first day, price went up by 30% over 1.5 days
+        let query_msg = QueryMsg::EstimatedApr {
+            time: first_day + 86400 + 43200,
+            window: 86400,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::EstimatedApr { apr, since } => {
+                assert_eq!(since, first_day);
+                assert_eq!(apr, Uint128(73_000_000_000_000_000_000));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // No history that far back
+        let query_msg = QueryMsg::EstimatedApr {
+            time: first_day,
+            window: 100 * 86400,
+        };
+        assert!(query(&deps, query_msg).is_err());
+    }
+
+    #[test]
+    fn test_handle_batch_bond() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let batch_msg = |amount: u128, bar_amount: u128| HandleMsg::Receive {
+            sender: HumanAddr("payroll".to_string()),
+            from: Default::default(),
+            amount: Uint128(amount),
+            msg: Some(
+                to_binary(&StakeReceiveType::BatchBond {
+                    bonds: vec![
+                        crate::batch::BatchBondAction {
+                            recipient: HumanAddr("foo".to_string()),
+                            amount: Uint128(60 * 10u128.pow(8)),
+                        },
+                        crate::batch::BatchBondAction {
+                            recipient: HumanAddr("bar".to_string()),
+                            amount: Uint128(bar_amount),
+                        },
+                    ],
+                })
+                .unwrap(),
+            ),
+            memo: None,
+            padding: None,
+        };
+
+        // Amounts must add up to what was received
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            batch_msg(100 * 10u128.pow(8), 50 * 10u128.pow(8)),
+        );
+        assert!(handle_result.is_err());
+
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            batch_msg(100 * 10u128.pow(8), 40 * 10u128.pow(8)),
+        );
+        assert!(handle_result.is_ok());
+        check_staked_state(
+            &deps,
+            Uint128(100 * 10u128.pow(8)),
+            Uint128(100 * 10u128.pow(18)),
+        );
+
+        let handle_msg = HandleMsg::SetViewingKey {
+            key: "key".to_string(),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bar", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("bar".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked { tokens, shares, .. } => {
+                assert_eq!(tokens, Uint128(40 * 10u128.pow(8)));
+                assert_eq!(shares, Uint128(40 * 10u128.pow(18)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: Some(BondLimits {
+                min_bond: Some(Uint128(50 * 10u128.pow(8))),
+                max_bond_per_account: None,
+                max_total_staked: None,
+            }),
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // One bond under the minimum rejects the whole batch and names its recipient
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            batch_msg(100 * 10u128.pow(8), 40 * 10u128.pow(8)),
+        );
+        match handle_result {
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert!(msg.contains("\"code\":36"));
+                assert!(msg.contains("Bond to bar"));
+            }
+            _ => panic!("Expected an error"),
+        }
+        check_staked_state(
+            &deps,
+            Uint128(100 * 10u128.pow(8)),
+            Uint128(100 * 10u128.pow(18)),
+        );
+
+        // Batch bonds are stopped with the other bonds
+        let handle_msg = HandleMsg::SetContractStatus {
+            level: ContractStatusLevel::StopBonding,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            batch_msg(100 * 10u128.pow(8), 40 * 10u128.pow(8)),
+        );
+        assert!(handle_result.is_err());
+    }
+
+    #[test]
+    fn test_handle_reward_vesting() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: Some(100),
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let start = 1_600_000_000;
+        let env_at = |time: u64| {
+            let mut env = mock_env("foo", &[]);
+            env.block.time = time;
+            env
+        };
+
+        // Vesting rewards can't be claimed to a recipient
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: Some(HumanAddr("vault".to_string())),
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env_at(start), handle_msg);
+        match handle_result {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.contains("\"code\":45")),
+            _ => panic!("Expected an error"),
+        }
+
+        // Rewards are vested instead of sent
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env_at(start), handle_msg);
+        assert!(handle_result.unwrap().messages.is_empty());
+
+        let handle_msg = HandleMsg::ClaimVested { padding: None };
+        let handle_result = handle(&mut deps, env_at(start), handle_msg.clone());
+        assert!(handle_result.is_err());
+
+        // Half has unlocked
+        let handle_result = handle(&mut deps, env_at(start + 50), handle_msg.clone());
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        let query_msg = QueryMsg::TransactionHistory {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            page: None,
+            page_size: 10,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TransactionHistory { txs, .. } => {
+                assert_eq!(
+                    txs[0].action,
+                    crate::transaction_history::TxAction::ClaimReward {
+                        staker: HumanAddr("foo".to_string()),
+                        recipient: None,
+                    }
+                );
+                assert_eq!(txs[0].coins.amount, Uint128(5 * 10u128.pow(8)));
+                assert_eq!(txs[0].memo, Some("Vested rewards".to_string()));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: Some(start + 75),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                vesting, vested, ..
+            } => {
+                assert_eq!(vesting, Uint128(25 * 10u128.pow(7)));
+                assert_eq!(vested, Some(Uint128(25 * 10u128.pow(7))));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Everything left has unlocked
+        let handle_result = handle(&mut deps, env_at(start + 200), handle_msg.clone());
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        let handle_result = handle(&mut deps, env_at(start + 300), handle_msg.clone());
+        assert!(handle_result.is_err());
+
+        // Operator tips vest for the operator
+        let reward_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), reward_msg);
+        assert!(handle_result.is_ok());
+
+        let operator_msg = HandleMsg::SetOperator {
+            operator: HumanAddr("keeper".to_string()),
+            max_tip: 100,
+            expiration: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env_at(start + 300), operator_msg);
+        assert!(handle_result.is_ok());
+
+        let stake_for_msg = HandleMsg::StakeRewardsFor {
+            owner: HumanAddr("foo".to_string()),
+            tip: Some(Uint128(10u128.pow(7))),
+            padding: None,
+        };
+        let mut env = mock_env("keeper", &[]);
+        env.block.time = start + 300;
+        let handle_result = handle(&mut deps, env.clone(), stake_for_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        env.block.time = start + 400;
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+    }
+
+    #[test]
+    fn test_query_balance_with_rewards() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        fn balance_of(deps: &Extern<MockStorage, MockApi, MockQuerier>, msg: QueryMsg) -> Uint128 {
+            match from_binary(&query(deps, msg).unwrap()).unwrap() {
+                QueryAnswer::Balance { amount } => amount,
+                _ => panic!("Unexpected result from query"),
+            }
+        }
+
+        let balance_msg = QueryMsg::Balance {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+        };
+        let balance_with_rewards_msg = QueryMsg::BalanceWithRewards {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+
+        assert_eq!(
+            balance_of(&deps, balance_msg.clone()),
+            Uint128(100 * 10u128.pow(8))
+        );
+        assert_eq!(
+            balance_of(&deps, balance_with_rewards_msg),
+            Uint128(110 * 10u128.pow(8))
+        );
+
+        // Accounts without stake have nothing
+        let handle_msg = HandleMsg::SetViewingKey {
+            key: "key".to_string(),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bar", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+        let query_msg = QueryMsg::BalanceWithRewards {
+            address: HumanAddr("bar".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        assert_eq!(balance_of(&deps, query_msg), Uint128::zero());
+
+        // Rewards become part of the default balance
+        let mut config = Config::from_storage(&mut deps.storage);
+        let mut constants = config.constants().unwrap();
+        constants.balance_with_rewards = true;
+        config.set_constants(&constants).unwrap();
+
+        assert_eq!(balance_of(&deps, balance_msg), Uint128(110 * 10u128.pow(8)));
+    }
+
+    #[test]
+    fn test_handle_operators() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let reward_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), reward_msg.clone());
+        assert!(handle_result.is_ok());
+
+        // Only approved operators can act for the owner
+        let stake_for_msg = HandleMsg::StakeRewardsFor {
+            owner: HumanAddr("foo".to_string()),
+            tip: Some(Uint128(10u128.pow(7))),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("keeper", &[]), stake_for_msg.clone());
+        assert!(handle_result.is_err());
+
+        let handle_msg = HandleMsg::SetOperator {
+            operator: HumanAddr("keeper".to_string()),
+            max_tip: 10_001,
+            expiration: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_err());
+
+        let handle_msg = HandleMsg::SetOperator {
+            operator: HumanAddr("keeper".to_string()),
+            max_tip: 100,
+            expiration: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_msg = QueryMsg::Operator {
+            owner: HumanAddr("foo".to_string()),
+            operator: HumanAddr("keeper".to_string()),
+            key: "key".to_string(),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Operator { approval, .. } => {
+                assert_eq!(approval.unwrap().max_tip, 100);
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // The tip is sent to the operator and the rest is staked for the owner
+        let handle_result = handle(&mut deps, mock_env("keeper", &[]), stake_for_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 2);
+
+        let query_msg = QueryMsg::Balance {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Balance { amount } => {
+                assert_eq!(amount, Uint128(1099 * 10u128.pow(7)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let handle_result = handle(&mut deps, mock_env("token", &[]), reward_msg);
+        assert!(handle_result.is_ok());
+
+        // Tips over the approved maximum are rejected
+        let claim_for_msg = HandleMsg::ClaimRewardsFor {
+            owner: HumanAddr("foo".to_string()),
+            tip: Some(Uint128(2 * 10u128.pow(7))),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("keeper", &[]), claim_for_msg.clone());
+        assert!(handle_result.is_err());
+
+        let handle_msg = HandleMsg::RevokeOperator {
+            operator: HumanAddr("keeper".to_string()),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_result = handle(&mut deps, mock_env("keeper", &[]), claim_for_msg);
+        assert!(handle_result.is_err());
+    }
+
+    #[test]
+    fn test_handle_error_codes() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let error_msg = |result: StdResult<HandleResponse>| match result {
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            _ => panic!("Expected an error"),
+        };
+
+        // Accounts that never unbonded have nothing to claim
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(error_msg(handle_result).contains("\"code\":40"));
+
+        // Accounts that never staked have no funds
+        let handle_msg = HandleMsg::ClaimRewards {
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bar", &[]), handle_msg);
+        assert!(error_msg(handle_result).contains("\"code\":20"));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("foo".to_string()),
+            from: Default::default(),
+            amount: Uint128(100),
+            msg: Some(to_binary(&ReceiveType::Bond { useFrom: None }).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("not_token", &[]), handle_msg);
+        assert!(error_msg(handle_result).contains("\"error\":\"not_stake_token\""));
+    }
+
+    #[test]
+    fn test_query_audit_invariants() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "bar", "key", Uint128(50 * 10u128.pow(8)));
+        new_staked_account(&mut deps, "admin", "key", Uint128(10 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let audit = |deps: &Extern<MockStorage, MockApi, MockQuerier>,
+                     page: u32,
+                     totals: Option<AuditTotals>| {
+            let query_msg = QueryMsg::AuditInvariants {
+                address: HumanAddr("admin".to_string()),
+                key: "key".to_string(),
+                page,
+                page_size: 2,
+                totals,
+            };
+            match from_binary(&query(deps, query_msg).unwrap()).unwrap() {
+                QueryAnswer::AuditInvariants {
+                    totals,
+                    total_shares,
+                    total_supply,
+                    total_unbonding,
+                    holders,
+                    mismatches,
+                } => (
+                    totals,
+                    (total_shares, total_supply, total_unbonding),
+                    holders,
+                    mismatches,
+                ),
+                _ => panic!("Unexpected result from query"),
+            }
+        };
+
+        // Pages are walked in order carrying the totals
+        let (first, _, holders, mismatches) = audit(&deps, 0, None);
+        assert_eq!(holders, 3);
+        assert!(mismatches.is_empty());
+        assert_eq!(first.tokens, Uint128(140 * 10u128.pow(8)));
+
+        let (second, (total_shares, total_supply, total_unbonding), _, mismatches) =
+            audit(&deps, 1, Some(first.clone()));
+        assert!(mismatches.is_empty());
+        assert_eq!(second.shares, total_shares);
+        assert_eq!(second.tokens, total_supply);
+        assert_eq!(second.tokens, Uint128(150 * 10u128.pow(8)));
+        assert_eq!(second.unbonding, total_unbonding);
+        assert_eq!(second.unbonding_entries, 1);
+
+        // Pages past the last holder are empty and not compared again
+        let (past, _, _, mismatches) = audit(&deps, 5, Some(first.clone()));
+        assert!(mismatches.is_empty());
+        assert_eq!(past, first);
+
+        // Only the admin can audit
+        let query_msg = QueryMsg::AuditInvariants {
+            address: HumanAddr("bar".to_string()),
+            key: "key".to_string(),
+            page: 0,
+            page_size: 2,
+            totals: None,
+        };
+        assert!(query(&deps, query_msg).is_err());
+
+        // A holder whose shares no longer add up to the total is reported on the last page
+        UserShares(Uint128(50 * 10u128.pow(18) + 1))
+            .save(&mut deps.storage, b"bar")
+            .unwrap();
+
+        let (first, _, _, mismatches) = audit(&deps, 0, None);
+        assert!(mismatches.is_empty());
+        let (_, _, _, mismatches) = audit(&deps, 1, Some(first));
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].starts_with("Holder shares add up to"));
+
+        // Holders that do not fit the global state are reported on their own page
+        UserShares(Uint128(200 * 10u128.pow(18)))
+            .save(&mut deps.storage, b"bar")
+            .unwrap();
+
+        let (_, _, _, mismatches) = audit(&deps, 0, None);
+        assert_eq!(mismatches.len(), 1);
+    }
+
+    #[test]
+    fn test_query_exchange_rate() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let exchange_rate = |deps: &Extern<MockStorage, MockApi, MockQuerier>| match from_binary(
+            &query(deps, QueryMsg::ExchangeRate { time: None }).unwrap(),
+        )
+        .unwrap()
+        {
+            QueryAnswer::ExchangeRate {
+                rate,
+                shares_per_token,
+                last_update,
+                ..
+            } => (rate, shares_per_token, last_update),
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Empty pools use the initial rate
+        assert_eq!(
+            exchange_rate(&deps),
+            (
+                Uint128(10u128.pow(18)),
+                Uint128(10u128.pow(18)),
+                mock_env("instantiator", &[]).block.time
+            )
+        );
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Reward).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let mut env = mock_env("token", &[]);
+        env.block.time += 100;
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.is_ok());
+
+        assert_eq!(
+            exchange_rate(&deps),
+            (
+                Uint128(11 * 10u128.pow(17)),
+                Uint128(909_090_909_090_909_090),
+                env.block.time
+            )
+        );
+
+        // Shares left without tokens have no rate and can't be bonded into
+        TotalTokens(Uint128::zero())
+            .save(&mut deps.storage)
+            .unwrap();
+        assert_eq!(
+            exchange_rate(&deps),
+            (Uint128::zero(), Uint128::zero(), env.block.time)
+        );
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("bar".to_string()),
+            from: Default::default(),
+            amount: Uint128(100 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Bond { useFrom: None }).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        match handle_result {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.contains("\"code\":35")),
+            _ => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    fn test_handle_cancel_unbond() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let mut env = mock_env("foo", &[]);
+        for amount in &[30, 20] {
+            let handle_msg = HandleMsg::Unbond {
+                amount: Uint128(amount * 10u128.pow(8)),
+                padding: None,
+            };
+            env.block.time += 1;
+            let handle_result = handle(&mut deps, env.clone(), handle_msg);
+            assert!(handle_result.is_ok());
+        }
+
+        // Partly fund the day
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(25 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::CancelUnbond {
+            amount: Uint128(60 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.is_err());
+
+        // Takes the newest unbonding and part of the older one,
+        // the funding the day no longer needs goes back to the treasury
+        let handle_msg = HandleMsg::CancelUnbond {
+            amount: Uint128(40 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert_eq!(handle_result.unwrap().messages.len(), 1);
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked {
+                tokens, unbonding, ..
+            } => {
+                assert_eq!(tokens, Uint128(90 * 10u128.pow(8)));
+                assert_eq!(unbonding, Uint128(10 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let query_response = query(&deps, QueryMsg::Unbonding {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Unbonding { total } => {
+                assert_eq!(total, Uint128(10 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // The rest of the day is already funded and can be claimed once released
+        env.block.time += 10;
+        let handle_result = handle(
+            &mut deps,
+            env,
+            HandleMsg::ClaimUnbond {
+                ids: None,
+                amount: None,
+                recipient: None,
+                recipient_code_hash: None,
+                msg: None,
+                padding: None,
+            },
+        );
+        assert!(handle_result.is_ok());
+    }
+
+    #[test]
+    fn test_handle_claim_unbond_ids() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let mut env = mock_env("foo", &[]);
+        for (id, amount) in [10, 20, 30].iter().enumerate() {
+            let handle_msg = HandleMsg::Unbond {
+                amount: Uint128(amount * 10u128.pow(8)),
+                padding: None,
+            };
+            env.block.time += 1;
+            let handle_result = handle(&mut deps, env.clone(), handle_msg);
+            match from_binary(&handle_result.unwrap().data.unwrap()).unwrap() {
+                HandleAnswer::Unbond { id: unbond_id, .. } => assert_eq!(unbond_id, id as u64),
+                _ => panic!("Unexpected result from handle"),
+            }
+        }
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(60 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Claim the newest unbonding only
+        env.block.time = 20;
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: Some(vec![2]),
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        match from_binary(&handle_result.unwrap().data.unwrap()).unwrap() {
+            HandleAnswer::ClaimUnbond { ids, .. } => assert_eq!(ids, vec![2]),
+            _ => panic!("Unexpected result from handle"),
+        }
+
+        // Already claimed
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.is_err());
+
+        // Claim part of the remaining ones, oldest first
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: Some(Uint128(15 * 10u128.pow(8))),
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        match from_binary(&handle_result.unwrap().data.unwrap()).unwrap() {
+            HandleAnswer::ClaimUnbond { ids, .. } => assert_eq!(ids, vec![0, 1]),
+            _ => panic!("Unexpected result from handle"),
+        }
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked { unbondings, .. } => {
+                assert_eq!(
+                    unbondings,
+                    vec![UnbondingEntry {
+                        id: 1,
+                        amount: Uint128(15 * 10u128.pow(8)),
+                        release: 12,
+                    }]
+                );
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: Some(vec![1, 5]),
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_err());
+
+        // A claimed ID is not handed out again for the same release
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: Some(vec![1]),
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Unbondings released at the same time keep their own entries and IDs
+        env.block.time = 2;
+        for (id, amount) in [(3, 5), (4, 7)].iter() {
+            let handle_msg = HandleMsg::Unbond {
+                amount: Uint128(amount * 10u128.pow(8)),
+                padding: None,
+            };
+            let handle_result = handle(&mut deps, env.clone(), handle_msg);
+            match from_binary(&handle_result.unwrap().data.unwrap()).unwrap() {
+                HandleAnswer::Unbond { id: unbond_id, .. } => assert_eq!(unbond_id, *id),
+                _ => panic!("Unexpected result from handle"),
+            }
+        }
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked { unbondings, .. } => {
+                assert_eq!(
+                    unbondings,
+                    vec![
+                        UnbondingEntry {
+                            id: 3,
+                            amount: Uint128(5 * 10u128.pow(8)),
+                            release: 12,
+                        },
+                        UnbondingEntry {
+                            id: 4,
+                            amount: Uint128(7 * 10u128.pow(8)),
+                            release: 12,
+                        },
+                    ]
+                );
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Cancelling takes back the newest entry first
+        let handle_msg = HandleMsg::CancelUnbond {
+            amount: Uint128(7 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_msg = QueryMsg::Staked {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            time: None,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Staked { unbondings, .. } => {
+                let ids: Vec<u64> = unbondings.iter().map(|entry| entry.id).collect();
+                assert_eq!(ids, vec![3]);
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
+    #[test]
+    fn test_handle_fund_unbonding_days_in_order() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        // Unbond on two different days
+        let mut env = mock_env("foo", &[]);
+        for (time, amount) in &[(0, 10), (86400, 20)] {
+            let handle_msg = HandleMsg::Unbond {
+                amount: Uint128(amount * 10u128.pow(8)),
+                padding: None,
+            };
+            env.block.time = *time;
+            let handle_result = handle(&mut deps, env.clone(), handle_msg);
+            assert!(handle_result.is_ok());
+        }
+
+        let fund_msg = |amount: u128| HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(amount * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+
+        // Only covers the first day
+        let handle_result = handle(&mut deps, mock_env("token", &[]), fund_msg(10));
+        assert!(handle_result.is_ok());
+
+        let query_response = query(
+            &deps,
+            QueryMsg::Unfunded {
+                start: 0,
+                total: 10,
+            },
+        )
+        .unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Unfunded { total } => assert_eq!(total, Uint128(20 * 10u128.pow(8))),
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Both are released but only the first day is funded
+        env.block.time = 86400 * 2;
+        let claim_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), claim_msg.clone());
+        match from_binary(&handle_result.unwrap().data.unwrap()).unwrap() {
+            HandleAnswer::ClaimUnbond { ids, .. } => assert_eq!(ids, vec![0]),
+            _ => panic!("Unexpected result from handle"),
+        }
+
+        let handle_result = handle(&mut deps, env.clone(), claim_msg.clone());
+        assert!(handle_result.is_err());
+
+        let handle_result = handle(&mut deps, mock_env("token", &[]), fund_msg(20));
+        assert!(handle_result.is_ok());
+
+        let handle_result = handle(&mut deps, env, claim_msg);
+        match from_binary(&handle_result.unwrap().data.unwrap()).unwrap() {
+            HandleAnswer::ClaimUnbond { ids, .. } => assert_eq!(ids, vec![1]),
+            _ => panic!("Unexpected result from handle"),
+        }
+    }
+
+    #[test]
+    fn test_handle_unfunded_days_out_of_order() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let config_msg = |unbond_time: u64| HandleMsg::UpdateStakeConfig {
+            unbond_time: Some(unbond_time),
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+
+        // Lowering the unbond time links the new epochs before the pending ones
+        let mut env = mock_env("foo", &[]);
+        env.block.time = 0;
+        for (days, amount) in &[(3, 10), (1, 20), (2, 30)] {
+            let handle_result = handle(&mut deps, mock_env("admin", &[]), config_msg(days * 86400));
+            assert!(handle_result.is_ok());
+
+            let handle_msg = HandleMsg::Unbond {
+                amount: Uint128(amount * 10u128.pow(8)),
+                padding: None,
+            };
+            let handle_result = handle(&mut deps, env.clone(), handle_msg);
+            assert!(handle_result.is_ok());
+        }
+
+        let schedule = |deps: &Extern<MockStorage, MockApi, MockQuerier>| {
+            let query_msg = QueryMsg::UnbondingSchedule {
+                start_after: None,
+                limit: 10,
+                time: 0,
+            };
+            match from_binary(&query(deps, query_msg).unwrap()).unwrap() {
+                QueryAnswer::UnbondingSchedule { entries, .. } => entries
+                    .iter()
+                    .map(|entry| entry.release)
+                    .collect::<Vec<u64>>(),
+                _ => panic!("Unexpected result from query"),
+            }
+        };
+        assert_eq!(schedule(&deps), vec![86400, 2 * 86400, 3 * 86400]);
+
+        // Funding starts with the oldest epoch
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(20 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+        assert_eq!(schedule(&deps), vec![2 * 86400, 3 * 86400]);
+
+        // Cancelling the newest unbonding leaves nothing owed for its epoch
+        let handle_msg = HandleMsg::CancelUnbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.is_ok());
+        assert_eq!(schedule(&deps), vec![2 * 86400]);
+
+        // A fully funded epoch owed again is linked back in
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), config_msg(86400));
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(5 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env, handle_msg);
+        assert!(handle_result.is_ok());
+        assert_eq!(schedule(&deps), vec![86400, 2 * 86400]);
+    }
+
+    #[test]
+    fn test_handle_update_unbonding_epoch() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let mut env = mock_env("foo", &[]);
+        env.block.time = 0;
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.is_ok());
+
+        // Hourly epochs start once the day of the pending unbonding is over
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: Some(3600),
+            treasury_callback: None,
+            padding: None,
+        };
+        let mut admin_env = mock_env("admin", &[]);
+        admin_env.block.time = 0;
+        let handle_result = handle(&mut deps, admin_env, handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_response = query(&deps, QueryMsg::StakeConfig {}).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::StakedConfig {
+                unbonding_epochs, ..
+            } => assert_eq!(
+                unbonding_epochs,
+                vec![
+                    UnbondingEpoch {
+                        start: 0,
+                        length: 86400,
+                    },
+                    UnbondingEpoch {
+                        start: 86400,
+                        length: 3600,
+                    },
+                ]
+            ),
+            _ => panic!("Unexpected result from query"),
+        };
+
+        // Released in two different hours of the second day
+        for (time, amount) in &[(86400 + 100, 20), (86400 + 3700, 30)] {
+            let handle_msg = HandleMsg::Unbond {
+                amount: Uint128(amount * 10u128.pow(8)),
+                padding: None,
+            };
+            env.block.time = *time;
+            let handle_result = handle(&mut deps, env.clone(), handle_msg);
+            assert!(handle_result.is_ok());
+        }
+
+        let fund_msg = |amount: u128| HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(amount * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), fund_msg(30));
+        assert!(handle_result.is_ok());
+
+        // Starting inside an epoch includes it
+        let query_response = query(
+            &deps,
+            QueryMsg::Unfunded {
+                start: 86400 + 5,
+                total: 10,
+            },
+        )
+        .unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::Unfunded { total } => assert_eq!(total, Uint128(30 * 10u128.pow(8))),
+            _ => panic!("Unexpected result from query"),
+        };
+
+        env.block.time = 86400 * 2;
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: None,
+            recipient: None,
+            recipient_code_hash: None,
+            msg: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env, handle_msg);
+        match from_binary(&handle_result.unwrap().data.unwrap()).unwrap() {
+            HandleAnswer::ClaimUnbond { ids, .. } => assert_eq!(ids, vec![0, 1]),
+            _ => panic!("Unexpected result from handle"),
+        }
+    }
+
+    #[test]
+    fn test_handle_treasury_callback() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        let update_msg = |code_hash: Option<&str>| HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: Some(TreasuryCallback {
+                code_hash: code_hash.map(|code_hash| code_hash.to_string()),
+            }),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), update_msg(Some("hash")));
+        assert!(handle_result.is_ok());
+
+        let notification = |msg: TreasuryHandleMsg| {
+            msg.to_cosmos_msg("hash".to_string(), HumanAddr("treasury".to_string()), None)
+                .unwrap()
+        };
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("foo".to_string()),
+            from: Default::default(),
+            amount: Uint128(100 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Bond { useFrom: None }).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.unwrap().messages.contains(&notification(
+            TreasuryHandleMsg::Bonded {
+                amount: Uint128(100 * 10u128.pow(8)),
+            }
+        )));
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let mut env = mock_env("foo", &[]);
+        env.block.time = 0;
+        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        assert!(handle_result.unwrap().messages.contains(&notification(
+            TreasuryHandleMsg::Unbonded {
+                amount: Uint128(10 * 10u128.pow(8)),
+                release: 0,
+            }
+        )));
+
+        // Cancelled unbondings are bonded at the treasury again
+        let handle_msg = HandleMsg::CancelUnbond {
+            amount: Uint128(5 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        assert!(handle_result.unwrap().messages.contains(&notification(
+            TreasuryHandleMsg::Bonded {
+                amount: Uint128(5 * 10u128.pow(8)),
+            }
+        )));
+
+        // Only what reaches the treasury is reported, without the fee
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: None,
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: Some(StakeFees {
+                bond: 100,
+                unbond: 0,
+            }),
+            instant_unbond: Some(InstantUnbondConfig {
+                penalty: 0,
+                buffer: Uint128(30 * 10u128.pow(8)),
+            }),
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let bond_msg = |amount: u128| HandleMsg::Receive {
+            sender: HumanAddr("bar".to_string()),
+            from: Default::default(),
+            amount: Uint128(amount),
+            msg: Some(to_binary(&ReceiveType::Bond { useFrom: None }).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            bond_msg(20 * 10u128.pow(8)),
+        );
+        assert!(handle_result.unwrap().messages.is_empty());
+
+        let handle_result = handle(
+            &mut deps,
+            mock_env("token", &[]),
+            bond_msg(100 * 10u128.pow(8)),
+        );
+        assert!(handle_result.unwrap().messages.contains(&notification(
+            TreasuryHandleMsg::Bonded {
+                amount: Uint128(89 * 10u128.pow(8)),
+            }
+        )));
+
+        // A new treasury needs its own code hash
+        let handle_msg = HandleMsg::UpdateStakeConfig {
+            unbond_time: None,
+            disable_treasury: false,
+            treasury: Some(HumanAddr("new_treasury".to_string())),
+            reward_drip_period: None,
+            lock_tiers: None,
+            bond_limits: None,
+            fees: None,
+            instant_unbond: None,
+            reward_vesting_period: None,
+            unbonding_epoch: None,
+            treasury_callback: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+        assert_eq!(
+            TreasuryCallback::load(&deps.storage).unwrap(),
+            TreasuryCallback::default()
+        );
+
+        let unbond_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env.clone(), unbond_msg.clone());
+        assert!(handle_result.unwrap().messages.is_empty());
+
+        // No notifications once the code hash is removed
+        let handle_result = handle(&mut deps, mock_env("admin", &[]), update_msg(None));
+        assert!(handle_result.is_ok());
+
+        let handle_result = handle(&mut deps, env, unbond_msg);
+        assert!(handle_result.unwrap().messages.is_empty());
+    }
+
+    #[test]
+    fn test_query_unbonding_schedule() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let mut env = mock_env("foo", &[]);
+        for (time, amount) in &[(0, 10), (86400, 20), (86400 * 2, 30)] {
+            let handle_msg = HandleMsg::Unbond {
+                amount: Uint128(amount * 10u128.pow(8)),
+                padding: None,
+            };
+            env.block.time = *time;
+            let handle_result = handle(&mut deps, env.clone(), handle_msg);
+            assert!(handle_result.is_ok());
+        }
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(5 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let query_msg = QueryMsg::UnbondingSchedule {
+            start_after: None,
+            limit: 2,
+            time: 86400 + 5,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::UnbondingSchedule {
+                entries,
+                overdue,
+                upcoming,
+            } => {
+                assert_eq!(
+                    entries,
+                    vec![
+                        ScheduledUnbonding {
+                            release: 0,
+                            unbonding: Uint128(10 * 10u128.pow(8)),
+                            funded: Uint128(5 * 10u128.pow(8)),
+                            remaining: Uint128(5 * 10u128.pow(8)),
+                        },
+                        ScheduledUnbonding {
+                            release: 86400,
+                            unbonding: Uint128(20 * 10u128.pow(8)),
+                            funded: Uint128::zero(),
+                            remaining: Uint128(20 * 10u128.pow(8)),
+                        },
+                    ]
+                );
+                // Totals only cover the listed page
+                assert_eq!(overdue, Uint128(25 * 10u128.pow(8)));
+                assert_eq!(upcoming, Uint128::zero());
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+
+        let schedule = |deps: &Extern<MockStorage, MockApi, MockQuerier>,
+                        start_after: Option<u64>,
+                        limit: u32| {
+            let query_msg = QueryMsg::UnbondingSchedule {
+                start_after,
+                limit,
+                time: 86400 + 5,
+            };
+            match from_binary(&query(deps, query_msg).unwrap()).unwrap() {
+                QueryAnswer::UnbondingSchedule {
+                    entries,
+                    overdue,
+                    upcoming,
+                } => (
+                    entries
+                        .iter()
+                        .map(|entry| entry.release)
+                        .collect::<Vec<u64>>(),
+                    overdue,
+                    upcoming,
+                ),
+                _ => panic!("Unexpected result from query"),
+            }
+        };
+
+        let (releases, overdue, upcoming) = schedule(&deps, Some(86400), 2);
+        assert_eq!(releases, vec![86400 * 2]);
+        assert_eq!(overdue, Uint128::zero());
+        assert_eq!(upcoming, Uint128(30 * 10u128.pow(8)));
+
+        // Pages can start between listed epochs
+        let (releases, _, _) = schedule(&deps, Some(5), 2);
+        assert_eq!(releases, vec![86400, 86400 * 2]);
+
+        // Limits over the cap are capped instead of rejected
+        let (releases, _, _) = schedule(&deps, None, u32::MAX);
+        assert_eq!(releases, vec![0, 86400, 86400 * 2]);
+
+        // A page can start after an epoch funded since it was listed
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(5 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let (releases, _, _) = schedule(&deps, Some(0), 2);
+        assert_eq!(releases, vec![86400, 86400 * 2]);
+    }
+
+    #[test]
+    fn test_handle_claim_unbond_into_contract() {
+        let (init_result, mut deps) = init_helper_staking();
+
+        new_staked_account(&mut deps, "foo", "key", Uint128(100 * 10u128.pow(8)));
+
+        let handle_msg = HandleMsg::Unbond {
+            amount: Uint128(10 * 10u128.pow(8)),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("foo", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let handle_msg = HandleMsg::Receive {
+            sender: HumanAddr("treasury".to_string()),
+            from: Default::default(),
+            amount: Uint128(10 * 10u128.pow(8)),
+            msg: Some(to_binary(&ReceiveType::Unbond).unwrap()),
+            memo: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("token", &[]), handle_msg);
+        assert!(handle_result.is_ok());
+
+        let mut env = mock_env("foo", &[]);
+        env.block.time = 20;
+        let handle_msg = HandleMsg::ClaimUnbond {
+            ids: None,
+            amount: None,
+            recipient: Some(HumanAddr("vault".to_string())),
+            recipient_code_hash: Some("vault_hash".to_string()),
+            msg: Some(Binary::from(b"deposit".to_vec())),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, env, handle_msg);
+        let messages = handle_result.unwrap().messages;
+        assert_eq!(messages.len(), 1);
+        match &messages[0] {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                callback_code_hash,
+                msg,
+                ..
+            }) => {
+                assert_eq!(contract_addr, &HumanAddr("token".to_string()));
+                assert_eq!(callback_code_hash, "hash");
+                let msg = String::from_utf8(msg.0.clone()).unwrap();
+                assert!(msg.contains("\"recipient\":\"vault\""));
+                assert!(msg.contains("\"recipient_code_hash\":\"vault_hash\""));
+                assert!(msg.contains(&format!(
+                    "\"msg\":\"{}\"",
+                    Binary::from(b"deposit".to_vec())
+                )));
+            }
+            _ => panic!("Unexpected message"),
+        }
+
+        let query_msg = QueryMsg::TransactionHistory {
+            address: HumanAddr("foo".to_string()),
+            key: "key".to_string(),
+            page: None,
+            page_size: 1,
+        };
+        let query_response = query(&deps, query_msg).unwrap();
+        match from_binary(&query_response).unwrap() {
+            QueryAnswer::TransactionHistory { txs, .. } => {
+                assert_eq!(
+                    txs[0].action,
+                    crate::transaction_history::TxAction::ClaimUnbond {
+                        staker: HumanAddr("foo".to_string()),
+                        recipient: Some(HumanAddr("vault".to_string())),
+                    }
+                );
+                assert_eq!(txs[0].coins.amount, Uint128(10 * 10u128.pow(8)));
+            }
+            _ => panic!("Unexpected result from query"),
+        };
+    }
+
     #[test]
     fn test_handle_set_distributors_status() {
         let (init_result, mut deps) = init_helper_staking();
@@ -3155,8 +6657,8 @@ mod snip20_tests {
         match handle_result {
             HandleAnswer::UpdateStakeConfig { status }
             | HandleAnswer::Receive { status }
-            | HandleAnswer::Unbond { status }
-            | HandleAnswer::ClaimUnbond { status }
+            | HandleAnswer::Unbond { status, .. }
+            | HandleAnswer::ClaimUnbond { status, .. }
             | HandleAnswer::ClaimRewards { status }
             | HandleAnswer::StakeRewards { status }
             | HandleAnswer::ExposeBalance { status }
@@ -4074,7 +7576,7 @@ mod snip20_tests {
         let error = extract_error_msg(handle_result);
         assert_eq!(
             error,
-            "This contract is stopped and this action is not allowed".to_string()
+            "{\"code\":0,\"error\":\"contract_stopped\",\"msg\":\"This contract is stopped and this action is not allowed\"}".to_string()
         );
     }
 
@@ -4262,8 +7764,10 @@ mod snip20_tests {
         match query_answer {
             QueryAnswer::TokenConfig {
                 public_total_supply,
+                balance_with_rewards,
             } => {
                 assert_eq!(public_total_supply, true);
+                assert_eq!(balance_with_rewards, false);
             }
             _ => panic!("unexpected"),
         }
@@ -0,0 +1,288 @@
+use cosmwasm_std::{to_vec, HumanAddr, StdError, Uint128};
+use serde::Serialize;
+
+///
+/// Contract errors, serialized into the error message as
+/// `{"code":<code>,"error":"<name>","msg":"<description>"}`
+///
+/// Codes are stable so integrators can match on them instead of the description
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractError {
+    // Access
+    ContractStopped,
+    NotAdmin,
+    NotOperator {
+        operator: HumanAddr,
+        owner: HumanAddr,
+    },
+
+    // Receiving
+    NotStakeToken,
+    NoReceiveType,
+    NotReward,
+
+    // Balances
+    NoFunds,
+    InsufficientShares,
+    InsufficientTokens,
+    InsufficientUnlockedTokens,
+    TokensLocked,
+    BalanceUnderflow,
+    BalanceOverflow,
+
+    // Bonding
+    BondBelowMinimum {
+        min_bond: Uint128,
+    },
+    BatchBondMismatch,
+    NoStakers,
+    PoolBelowMinimum {
+        minimum: Uint128,
+    },
+    BondOverLimit {
+        room: Uint128,
+    },
+    UnbackedShares,
+    BatchBondBelowMinimum {
+        recipient: HumanAddr,
+        min_bond: Uint128,
+    },
+
+    // Claiming
+    NothingToClaim,
+    InsufficientLiquidity,
+    TipTooHigh {
+        max_tip: u64,
+    },
+    InsufficientUnbonding,
+    UnbondingNotClaimable {
+        id: u64,
+    },
+    VestingRecipient,
+
+    // Admin
+    InvalidConfig(&'static str),
+    NoLockTier,
+    LossTooLarge,
+    NoRoundingDust,
+    NoTreasury,
+    StakeTokenReward,
+
+    // Queries
+    NoRateHistory,
+    NotEnoughRateHistory,
+
+    // Math
+    Overflow(&'static str),
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+    code: u16,
+    error: &'static str,
+    msg: String,
+}
+
+impl ContractError {
+    ///
+    /// Returns the stable code, name and description of the error
+    ///
+    fn details(&self) -> (u16, &'static str, String) {
+        match self {
+            Self::ContractStopped => (
+                0,
+                "contract_stopped",
+                "This contract is stopped and this action is not allowed".to_string(),
+            ),
+            Self::NotAdmin => (
+                1,
+                "not_admin",
+                "This is an admin command. Admin commands can only be run from admin address"
+                    .to_string(),
+            ),
+            Self::NotOperator { operator, owner } => (
+                2,
+                "not_operator",
+                format!("{} is not an approved operator for {}", operator, owner),
+            ),
+            Self::NotStakeToken => (10, "not_stake_token", "Not the stake token".to_string()),
+            Self::NoReceiveType => (
+                11,
+                "no_receive_type",
+                "No receive type supplied in message".to_string(),
+            ),
+            Self::NotReward => (
+                12,
+                "not_reward",
+                "Reward tokens can only be received as rewards".to_string(),
+            ),
+            Self::NoFunds => (20, "no_funds", "No funds".to_string()),
+            Self::InsufficientShares => {
+                (21, "insufficient_shares", "Insufficient shares".to_string())
+            }
+            Self::InsufficientTokens => {
+                (22, "insufficient_tokens", "Insufficient tokens".to_string())
+            }
+            Self::InsufficientUnlockedTokens => (
+                23,
+                "insufficient_unlocked_tokens",
+                "Insufficient unlocked tokens".to_string(),
+            ),
+            Self::TokensLocked => (24, "tokens_locked", "Tokens are locked".to_string()),
+            Self::BalanceUnderflow => (
+                25,
+                "balance_underflow",
+                "This burn attempt would decrease the account's balance to a negative".to_string(),
+            ),
+            Self::BalanceOverflow => (
+                26,
+                "balance_overflow",
+                "This mint attempt would increase the account's balance above the supported maximum"
+                    .to_string(),
+            ),
+            Self::BondBelowMinimum { min_bond } => (
+                30,
+                "bond_below_minimum",
+                format!("Bond is below the minimum of {}", min_bond),
+            ),
+            Self::BatchBondMismatch => (
+                31,
+                "batch_bond_mismatch",
+                "Bond amounts must add up to the received amount".to_string(),
+            ),
+            Self::NoStakers => (32, "no_stakers", "No stakers to reward".to_string()),
+            Self::PoolBelowMinimum { minimum } => (
+                33,
+                "pool_below_minimum",
+                format!("Staked supply cannot be left between zero and {}", minimum),
+            ),
+            Self::BondOverLimit { room } => (
+                34,
+                "bond_over_limit",
+                format!("Only {} more can be bonded under the bond limits", room),
+            ),
+            Self::UnbackedShares => (
+                35,
+                "unbacked_shares",
+                "Staked shares are not backed by any tokens".to_string(),
+            ),
+            Self::BatchBondBelowMinimum {
+                recipient,
+                min_bond,
+            } => (
+                36,
+                "batch_bond_below_minimum",
+                format!(
+                    "Bond to {} is below the minimum of {}, the batch was rejected",
+                    recipient, min_bond
+                ),
+            ),
+            Self::NothingToClaim => (40, "nothing_to_claim", "Nothing to claim".to_string()),
+            Self::InsufficientLiquidity => (
+                41,
+                "insufficient_liquidity",
+                "Not enough instant liquidity".to_string(),
+            ),
+            Self::TipTooHigh { max_tip } => (
+                42,
+                "tip_too_high",
+                format!(
+                    "Tip cannot be over {} basis points of the claimed rewards",
+                    max_tip
+                ),
+            ),
+            Self::InsufficientUnbonding => (
+                43,
+                "insufficient_unbonding",
+                "Not enough pending unbonding to cancel".to_string(),
+            ),
+            Self::UnbondingNotClaimable { id } => (
+                44,
+                "unbonding_not_claimable",
+                format!("Unbonding {} does not exist or cannot be claimed yet", id),
+            ),
+            Self::VestingRecipient => (
+                45,
+                "vesting_recipient",
+                "Vesting rewards cannot be sent to a recipient".to_string(),
+            ),
+            Self::InvalidConfig(msg) => (50, "invalid_config", msg.to_string()),
+            Self::NoLockTier => (
+                51,
+                "no_lock_tier",
+                "No lock tier with that duration".to_string(),
+            ),
+            Self::LossTooLarge => (
+                52,
+                "loss_too_large",
+                "Loss exceeds the staked tokens".to_string(),
+            ),
+            Self::NoRoundingDust => (
+                53,
+                "no_rounding_dust",
+                "No rounding dust to sweep".to_string(),
+            ),
+            Self::NoTreasury => (54, "no_treasury", "No treasury to sweep to".to_string()),
+            Self::StakeTokenReward => (
+                55,
+                "stake_token_reward",
+                "The staked token is already distributed as a reward".to_string(),
+            ),
+            Self::NoRateHistory => (
+                60,
+                "no_rate_history",
+                "No stake rate history for that window".to_string(),
+            ),
+            Self::NotEnoughRateHistory => (
+                61,
+                "not_enough_rate_history",
+                "Not enough stake rate history to estimate".to_string(),
+            ),
+            Self::Overflow(msg) => (70, "overflow", msg.to_string()),
+        }
+    }
+}
+
+impl From<ContractError> for StdError {
+    fn from(err: ContractError) -> Self {
+        let (code, error, msg) = err.details();
+        let message = ErrorMessage { code, error, msg };
+
+        // Serializing a flat struct of numbers and strings cannot fail
+        StdError::generic_err(
+            String::from_utf8(to_vec(&message).unwrap_or_default()).unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ContractError;
+    use cosmwasm_std::{HumanAddr, StdError};
+
+    #[test]
+    fn error_message_format() {
+        let err: StdError = ContractError::NothingToClaim.into();
+        match err {
+            StdError::GenericErr { msg, .. } => assert_eq!(
+                msg,
+                "{\"code\":40,\"error\":\"nothing_to_claim\",\"msg\":\"Nothing to claim\"}"
+            ),
+            _ => panic!("Unexpected error type"),
+        }
+
+        let err: StdError = ContractError::NotOperator {
+            operator: HumanAddr("keeper".to_string()),
+            owner: HumanAddr("foo".to_string()),
+        }
+        .into();
+        match err {
+            StdError::GenericErr { msg, .. } => assert_eq!(
+                msg,
+                "{\"code\":2,\"error\":\"not_operator\",\"msg\":\"keeper is not an approved operator for foo\"}"
+            ),
+            _ => panic!("Unexpected error type"),
+        }
+    }
+}
@@ -1,15 +1,18 @@
 mod batch;
 pub mod contract;
 mod distributors;
+mod errors;
 mod expose_balance;
 pub mod msg;
 mod rand;
 pub mod receiver;
+mod reward_tokens;
 mod stake;
 mod stake_queries;
 pub mod state;
 mod state_staking;
 mod transaction_history;
+mod treasury;
 mod utils;
 mod viewing_key;
 
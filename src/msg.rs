@@ -4,6 +4,11 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::batch;
+use crate::state::Operator;
+use crate::state_staking::{
+    BondLimits, InstantUnbondConfig, Lock, LockTier, RateSnapshot, ReportedLoss, StakeFees,
+    TreasuryCallback, UnbondingEpoch,
+};
 use crate::transaction_history::{RichTx, Tx};
 use crate::viewing_key::ViewingKey;
 use cosmwasm_std::{Binary, HumanAddr, StdError, StdResult, Uint128};
@@ -48,12 +53,19 @@ pub struct InitConfig {
     /// Indicates whether the total supply is public or should be kept secret.
     /// default: False
     pub public_total_supply: Option<bool>,
+    /// Indicates whether balance queries include pending rewards.
+    /// default: False
+    pub balance_with_rewards: Option<bool>,
 }
 
 impl InitConfig {
     pub fn public_total_supply(&self) -> bool {
         self.public_total_supply.unwrap_or(false)
     }
+
+    pub fn balance_with_rewards(&self) -> bool {
+        self.balance_with_rewards.unwrap_or(false)
+    }
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
@@ -64,6 +76,15 @@ pub enum HandleMsg {
         unbond_time: Option<u64>,
         disable_treasury: bool,
         treasury: Option<HumanAddr>,
+        reward_drip_period: Option<u64>,
+        lock_tiers: Option<Vec<LockTier>>,
+        bond_limits: Option<BondLimits>,
+        fees: Option<StakeFees>,
+        instant_unbond: Option<InstantUnbondConfig>,
+        reward_vesting_period: Option<u64>,
+        // Seconds per unbonding funding epoch
+        unbonding_epoch: Option<u64>,
+        treasury_callback: Option<TreasuryCallback>,
         padding: Option<String>,
     },
     Receive {
@@ -78,15 +99,81 @@ pub enum HandleMsg {
         amount: Uint128,
         padding: Option<String>,
     },
+    InstantUnbond {
+        amount: Uint128,
+        padding: Option<String>,
+    },
+    CancelUnbond {
+        amount: Uint128,
+        padding: Option<String>,
+    },
+    // Claims every released unbonding unless IDs or an amount are given
+    // A recipient contract is sent the tokens with msg when its code hash is given
     ClaimUnbond {
+        ids: Option<Vec<u64>>,
+        amount: Option<Uint128>,
+        recipient: Option<HumanAddr>,
+        recipient_code_hash: Option<String>,
+        msg: Option<Binary>,
         padding: Option<String>,
     },
+    // A recipient is rejected while rewards vest, they are added to the sender's vesting instead
     ClaimRewards {
+        recipient: Option<HumanAddr>,
+        recipient_code_hash: Option<String>,
+        msg: Option<Binary>,
+        padding: Option<String>,
+    },
+    ClaimVested {
         padding: Option<String>,
     },
     StakeRewards {
         padding: Option<String>,
     },
+    SetOperator {
+        operator: HumanAddr,
+        max_tip: u64,
+        expiration: Option<u64>,
+        padding: Option<String>,
+    },
+    RevokeOperator {
+        operator: HumanAddr,
+        padding: Option<String>,
+    },
+    ClaimRewardsFor {
+        owner: HumanAddr,
+        tip: Option<Uint128>,
+        padding: Option<String>,
+    },
+    StakeRewardsFor {
+        owner: HumanAddr,
+        tip: Option<Uint128>,
+        padding: Option<String>,
+    },
+    SetAutoCompound {
+        enabled: bool,
+        padding: Option<String>,
+    },
+    ReportLoss {
+        amount: Uint128,
+        memo: Option<String>,
+        padding: Option<String>,
+    },
+    LockStake {
+        amount: Uint128,
+        duration: u64,
+        padding: Option<String>,
+    },
+    SweepRoundingDust {
+        to_treasury: bool,
+        padding: Option<String>,
+    },
+
+    // Reward tokens
+    AddRewardTokens {
+        tokens: Vec<Contract>,
+        padding: Option<String>,
+    },
 
     // Balance
     ExposeBalance {
@@ -209,6 +296,21 @@ pub enum HandleMsg {
     },
 }
 
+// Receive msgs accepted on top of the shared ReceiveType
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StakeReceiveType {
+    // Splits the received tokens between the recipients, amounts must add up to the total
+    BatchBond {
+        bonds: Vec<batch::BatchBondAction>,
+    },
+    // Bonds like ReceiveType::Bond and locks the bonded tokens for a configured tier
+    BondAndLock {
+        use_from: Option<bool>,
+        duration: u64,
+    },
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum HandleAnswer {
@@ -220,16 +322,55 @@ pub enum HandleAnswer {
     },
     Unbond {
         status: ResponseStatus,
+        id: u64,
+    },
+    InstantUnbond {
+        status: ResponseStatus,
+    },
+    CancelUnbond {
+        status: ResponseStatus,
     },
     ClaimUnbond {
         status: ResponseStatus,
+        // IDs of the claimed unbondings, partly claimed ones included
+        ids: Vec<u64>,
     },
     ClaimRewards {
         status: ResponseStatus,
     },
+    ClaimVested {
+        status: ResponseStatus,
+    },
     StakeRewards {
         status: ResponseStatus,
     },
+    SetOperator {
+        status: ResponseStatus,
+    },
+    RevokeOperator {
+        status: ResponseStatus,
+    },
+    ClaimRewardsFor {
+        status: ResponseStatus,
+    },
+    StakeRewardsFor {
+        status: ResponseStatus,
+    },
+    SetAutoCompound {
+        status: ResponseStatus,
+    },
+    ReportLoss {
+        status: ResponseStatus,
+    },
+    LockStake {
+        status: ResponseStatus,
+    },
+    SweepRoundingDust {
+        status: ResponseStatus,
+    },
+    AddRewardTokens {
+        status: ResponseStatus,
+    },
     ExposeBalance {
         status: ResponseStatus,
     },
@@ -312,16 +453,65 @@ pub enum QueryMsg {
     TotalStaked {},
     // Total token shares per token
     StakeRate {},
+    // Share price in whole units, including the rewards dripped by the given time
+    ExchangeRate {
+        time: Option<u64>,
+    },
+    // Rewards not yet released into the total tokens
+    RewardDrip {
+        time: Option<u64>,
+    },
+    // Tokens truncated away from reward claims
+    RoundingDust {},
+    // Staked tokens available for instant unbonds
+    InstantLiquidity {},
+    // Daily share price snapshots, newest first
+    StakeRateHistory {
+        page: Option<u32>,
+        page_size: u32,
+    },
+    // Losses reported by the treasury, newest first
+    LossHistory {
+        page: Option<u32>,
+        page_size: u32,
+    },
+    // Share price growth since the window started, annualized
+    EstimatedApr {
+        time: u64,
+        window: u64,
+    },
     Unbonding {},
     Unfunded {
         start: u64,
         total: u64,
     },
+    // Unfunded epochs after start_after, oldest first and at most 100 per page
+    UnbondingSchedule {
+        start_after: Option<u64>,
+        limit: u32,
+        time: u64,
+    },
     Staked {
         address: HumanAddr,
         key: String,
         time: Option<u64>,
     },
+    Operator {
+        owner: HumanAddr,
+        operator: HumanAddr,
+        key: String,
+    },
+    // Admin only, pages are walked in order passing back the totals of the previous page
+    AuditInvariants {
+        address: HumanAddr,
+        key: String,
+        page: u32,
+        page_size: u32,
+        totals: Option<AuditTotals>,
+    },
+
+    // Reward tokens
+    RewardTokens {},
 
     // Distributors
     Distributors {},
@@ -339,6 +529,11 @@ pub enum QueryMsg {
         address: HumanAddr,
         key: String,
     },
+    BalanceWithRewards {
+        address: HumanAddr,
+        key: String,
+        time: Option<u64>,
+    },
     TransferHistory {
         address: HumanAddr,
         key: String,
@@ -361,7 +556,16 @@ impl QueryMsg {
     pub fn get_validation_params(&self) -> (Vec<&HumanAddr>, ViewingKey) {
         match self {
             Self::Staked { address, key, .. } => (vec![address], ViewingKey(key.clone())),
+            Self::Operator {
+                owner,
+                operator,
+                key,
+            } => (vec![owner, operator], ViewingKey(key.clone())),
+            Self::AuditInvariants { address, key, .. } => (vec![address], ViewingKey(key.clone())),
             Self::Balance { address, key } => (vec![address], ViewingKey(key.clone())),
+            Self::BalanceWithRewards { address, key, .. } => {
+                (vec![address], ViewingKey(key.clone()))
+            }
             Self::TransferHistory { address, key, .. } => (vec![address], ViewingKey(key.clone())),
             Self::TransactionHistory { address, key, .. } => {
                 (vec![address], ViewingKey(key.clone()))
@@ -390,6 +594,9 @@ pub enum QueryWithPermit {
         spender: HumanAddr,
     },
     Balance {},
+    BalanceWithRewards {
+        time: Option<u64>,
+    },
     TransferHistory {
         page: Option<u32>,
         page_size: u32,
@@ -406,6 +613,14 @@ pub enum QueryAnswer {
     // Stake
     StakedConfig {
         config: StakeConfig,
+        reward_drip_period: u64,
+        lock_tiers: Vec<LockTier>,
+        bond_limits: BondLimits,
+        fees: StakeFees,
+        instant_unbond: InstantUnbondConfig,
+        reward_vesting_period: u64,
+        unbonding_epochs: Vec<UnbondingEpoch>,
+        treasury_callback: TreasuryCallback,
     },
     TotalStaked {
         tokens: Uint128,
@@ -415,13 +630,63 @@ pub enum QueryAnswer {
     StakeRate {
         shares: Uint128,
     },
+    RewardDrip {
+        remaining: Uint128,
+        // Tokens released per second
+        rate: Uint128,
+        end: u64,
+    },
+    RoundingDust {
+        tokens: Uint128,
+        // Fraction of a token scaled by 10^18
+        remainder: Uint128,
+    },
+    InstantLiquidity {
+        amount: Uint128,
+    },
+    StakeRateHistory {
+        snapshots: Vec<RateSnapshot>,
+        total: u64,
+    },
+    LossHistory {
+        losses: Vec<ReportedLoss>,
+        total: u64,
+    },
+    EstimatedApr {
+        // Scaled by 10^18
+        apr: Uint128,
+        // Day of the snapshot the estimate starts from
+        since: u64,
+    },
     Staked {
         tokens: Uint128,
         shares: Uint128,
         pending_rewards: Uint128,
         unbonding: Uint128,
         unbonded: Option<Uint128>,
+        unbondings: Vec<UnbondingEntry>,
         cooldown: VecQueue<QueueItem>,
+        reward_tokens: Vec<PendingReward>,
+        auto_compound: bool,
+        locks: Vec<Lock>,
+        // Claimed rewards still locked
+        vesting: Uint128,
+        // Claimed rewards unlocked by the given time
+        vested: Option<Uint128>,
+    },
+    Operator {
+        owner: HumanAddr,
+        operator: HumanAddr,
+        approval: Option<Operator>,
+    },
+    AuditInvariants {
+        // Running sums up to and including this page
+        totals: AuditTotals,
+        total_shares: Uint128,
+        total_supply: Uint128,
+        total_unbonding: Uint128,
+        holders: u64,
+        mismatches: Vec<String>,
     },
     Unbonding {
         total: Uint128,
@@ -429,6 +694,18 @@ pub enum QueryAnswer {
     Unfunded {
         total: Uint128,
     },
+    UnbondingSchedule {
+        entries: Vec<ScheduledUnbonding>,
+        // Still owed for the listed epochs released by the given time
+        overdue: Uint128,
+        // Still owed for the listed epochs released after the given time
+        upcoming: Uint128,
+    },
+
+    // Reward tokens
+    RewardTokens {
+        tokens: Vec<Contract>,
+    },
 
     // Distributors
     Distributors {
@@ -444,13 +721,19 @@ pub enum QueryAnswer {
     },
     TokenConfig {
         public_total_supply: bool,
+        balance_with_rewards: bool,
     },
     ContractStatus {
         status: ContractStatusLevel,
     },
     ExchangeRate {
+        // Staked tokens per share, 18 decimal fixed point
         rate: Uint128,
         denom: String,
+        // Shares per staked token, 18 decimal fixed point
+        shares_per_token: Uint128,
+        // Block time of the last share price change
+        last_update: u64,
     },
     Allowance {
         spender: HumanAddr,
@@ -474,6 +757,41 @@ pub enum QueryAnswer {
     },
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AuditTotals {
+    pub shares: Uint128,
+    pub tokens: Uint128,
+    pub unbonding: Uint128,
+    // Each unbonding can be rounded down once by reported losses
+    pub unbonding_entries: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnbondingEntry {
+    pub id: u64,
+    pub amount: Uint128,
+    pub release: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ScheduledUnbonding {
+    // Start of the epoch
+    pub release: u64,
+    pub unbonding: Uint128,
+    pub funded: Uint128,
+    pub remaining: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PendingReward {
+    pub token: HumanAddr,
+    pub amount: Uint128,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 pub struct CreateViewingKeyResponse {
     pub key: String,
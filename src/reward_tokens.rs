@@ -0,0 +1,201 @@
+use crate::contract::check_if_admin;
+use crate::errors::ContractError;
+use crate::msg::ResponseStatus::Success;
+use crate::msg::{HandleAnswer, PendingReward, QueryAnswer};
+use crate::state::Config;
+use crate::state_staking::{RewardIndex, RewardTokens, TotalShares, UserReward};
+use cosmwasm_std::{
+    to_binary, Api, Binary, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult,
+    Storage, Uint128,
+};
+use ethnum::u256;
+use secret_toolkit::snip20::register_receive_msg;
+use shade_protocol::shd_staking::stake::StakeConfig;
+use shade_protocol::utils::asset::Contract;
+use shade_protocol::utils::storage::{BucketStorage, SingletonStorage};
+
+// Scales the reward index so small rewards over large share supplies are not lost
+const REWARD_INDEX_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+fn user_key(token: &HumanAddr, account: &HumanAddr) -> Vec<u8> {
+    [token.as_str().as_bytes(), account.as_str().as_bytes()].concat()
+}
+
+fn reward_index<S: Storage>(storage: &S, token: &HumanAddr) -> StdResult<Uint128> {
+    Ok(RewardIndex::may_load(storage, token.as_str().as_bytes())?
+        .unwrap_or(RewardIndex(Uint128::zero()))
+        .0)
+}
+
+///
+/// Returns the rewards earned by the shares between two indexes
+///
+fn accrued(shares: u128, index: Uint128, user_index: Uint128) -> StdResult<u128> {
+    let delta = u256::from((index - user_index)?.u128());
+    if let Some(reward) = u256::from(shares).checked_mul(delta) {
+        Ok((reward / u256::from(REWARD_INDEX_PRECISION)).as_u128())
+    } else {
+        Err(ContractError::Overflow("Reward calculation overflow").into())
+    }
+}
+
+///
+/// Spreads a reward deposit over the current total shares
+///
+pub fn add_token_reward<S: Storage>(
+    storage: &mut S,
+    token: &HumanAddr,
+    amount: Uint128,
+) -> StdResult<()> {
+    let total_shares = TotalShares::load(storage)?.0.u128();
+    if total_shares == 0 {
+        return Err(ContractError::NoStakers.into());
+    }
+
+    let scaled = u256::from(amount.u128())
+        .checked_mul(u256::from(REWARD_INDEX_PRECISION))
+        .ok_or_else(|| StdError::from(ContractError::Overflow("Reward calculation overflow")))?;
+
+    let index = reward_index(storage, token)?.u128();
+    if let Some(new_index) = index.checked_add((scaled / u256::from(total_shares)).as_u128()) {
+        RewardIndex(Uint128(new_index)).save(storage, token.as_str().as_bytes())?;
+    } else {
+        return Err(ContractError::Overflow("Reward index overflow").into());
+    }
+
+    Ok(())
+}
+
+///
+/// Moves the account's earned rewards into pending, must be called before its shares change
+///
+pub fn accrue_rewards<S: Storage>(
+    storage: &mut S,
+    account: &HumanAddr,
+    shares: u128,
+) -> StdResult<()> {
+    for token in RewardTokens::load(storage)?.0.iter() {
+        let index = reward_index(storage, &token.address)?;
+        let key = user_key(&token.address, account);
+        let mut user_reward = UserReward::may_load(storage, &key)?.unwrap_or(UserReward {
+            index: Uint128::zero(),
+            pending: Uint128::zero(),
+        });
+
+        user_reward.pending += Uint128(accrued(shares, index, user_reward.index)?);
+        user_reward.index = index;
+        user_reward.save(storage, &key)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Clears all of the account's pending rewards and returns what should be sent
+///
+pub fn claim_reward_tokens<S: Storage>(
+    storage: &mut S,
+    account: &HumanAddr,
+    shares: u128,
+) -> StdResult<Vec<(Contract, Uint128)>> {
+    accrue_rewards(storage, account, shares)?;
+
+    let mut claims = vec![];
+    for token in RewardTokens::load(storage)?.0 {
+        let key = user_key(&token.address, account);
+        if let Some(mut user_reward) = UserReward::may_load(storage, &key)? {
+            if user_reward.pending != Uint128::zero() {
+                claims.push((token, user_reward.pending));
+                user_reward.pending = Uint128::zero();
+                user_reward.save(storage, &key)?;
+            }
+        }
+    }
+
+    Ok(claims)
+}
+
+///
+/// Read only version of accrue_rewards used by queries
+///
+pub fn pending_reward_tokens<S: Storage>(
+    storage: &S,
+    account: &HumanAddr,
+    shares: u128,
+) -> StdResult<Vec<PendingReward>> {
+    let mut pending = vec![];
+    for token in RewardTokens::load(storage)?.0 {
+        let index = reward_index(storage, &token.address)?;
+        let (user_index, user_pending) =
+            match UserReward::may_load(storage, &user_key(&token.address, account))? {
+                Some(user_reward) => (user_reward.index, user_reward.pending),
+                None => (Uint128::zero(), Uint128::zero()),
+            };
+
+        pending.push(PendingReward {
+            amount: user_pending + Uint128(accrued(shares, index, user_index)?),
+            token: token.address,
+        });
+    }
+
+    Ok(pending)
+}
+
+pub fn is_reward_token<S: Storage>(storage: &S, token: &HumanAddr) -> StdResult<Option<Contract>> {
+    Ok(RewardTokens::load(storage)?
+        .0
+        .into_iter()
+        .find(|contract| &contract.address == token))
+}
+
+pub fn try_add_reward_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    tokens: Vec<Contract>,
+) -> StdResult<HandleResponse> {
+    let config = Config::from_storage(&mut deps.storage);
+
+    check_if_admin(&config, &env.message.sender)?;
+
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let mut reward_tokens = RewardTokens::load(&deps.storage)?;
+
+    let mut messages = vec![];
+    for token in tokens {
+        if token.address == stake_config.staked_token.address {
+            return Err(ContractError::StakeTokenReward.into());
+        }
+        if reward_tokens
+            .0
+            .iter()
+            .any(|contract| contract.address == token.address)
+        {
+            continue;
+        }
+
+        messages.push(register_receive_msg(
+            env.contract_code_hash.clone(),
+            None,
+            256,
+            token.code_hash.clone(),
+            token.address.clone(),
+        )?);
+        reward_tokens.0.push(token);
+    }
+
+    reward_tokens.save(&mut deps.storage)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::AddRewardTokens {
+            status: Success,
+        })?),
+    })
+}
+
+pub fn reward_tokens<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    to_binary(&QueryAnswer::RewardTokens {
+        tokens: RewardTokens::load(&deps.storage)?.0,
+    })
+}
@@ -1,33 +1,57 @@
+use crate::batch::BatchBondAction;
 use crate::contract::check_if_admin;
-use crate::msg::HandleAnswer;
+use crate::errors::ContractError;
 use crate::msg::ResponseStatus::Success;
-use crate::state::{Balances, Config, ReadonlyConfig};
+use crate::msg::{HandleAnswer, StakeReceiveType};
+use crate::reward_tokens::{
+    accrue_rewards, add_token_reward, claim_reward_tokens, is_reward_token,
+};
+use crate::state::{
+    read_operator, remove_operator, write_operator, Balances, Config, Operator, ReadonlyBalances,
+    ReadonlyConfig,
+};
 use crate::state_staking::{
-    DailyUnbondingQueue, TotalShares, TotalTokens, TotalUnbonding, UnbondingQueue,
-    UnsentStakedTokens, UserCooldown, UserShares,
+    AutoCompound, BondLimits, DailyUnbondingRecord, HeldRewards, Holder, HolderCount, HolderIndex,
+    InstantUnbondConfig, LastRateChange, LastUnbondingRelease, LiquidityBuffer, Lock, LockTier,
+    LockTiers, RateSnapshot, RateSnapshotCount, ReportedLoss, ReportedLossCount, RewardDrip,
+    RewardDripPeriod, RewardVestingPeriod, RoundingDust, StakeFees, TotalLockBonus, TotalShares,
+    TotalTokens, TotalUnbonding, TreasuryCallback, UnbondingCount, UnbondingEpoch, UnbondingEpochs,
+    UnbondingIds, UnbondingLoss, UnbondingQueue, UnfundedDay, UnfundedDays, UnsentStakedTokens,
+    UserCooldown, UserLocks, UserShares, UserVesting, Vesting,
 };
 use crate::transaction_history::{
-    store_add_reward, store_claim_reward, store_claim_unbond, store_fund_unbond, store_stake,
-    store_unbond,
+    store_add_reward, store_claim_reward, store_claim_unbond, store_compound, store_fee,
+    store_fund_unbond, store_lock, store_report_loss, store_stake, store_unbond, store_unlock,
 };
+use crate::treasury::{notify_treasury, TreasuryHandleMsg};
 use cosmwasm_std::{
-    from_binary, to_binary, Api, Binary, CanonicalAddr, Decimal, Env, Extern,
+    from_binary, to_binary, Api, Binary, BlockInfo, CanonicalAddr, CosmosMsg, Decimal, Env, Extern,
     HandleResponse, HumanAddr, Querier, StdError, StdResult, Storage, Uint128,
 };
 use ethnum::u256;
 use secret_toolkit::snip20::send_msg;
+use secret_toolkit::utils::HandleCallback;
+use serde::Serialize;
 use shade_protocol::shd_staking::stake::{DailyUnbonding, StakeConfig, Unbonding, VecQueue};
 use shade_protocol::shd_staking::ReceiveType;
+use shade_protocol::utils::asset::Contract;
 use shade_protocol::utils::storage::{BucketStorage, SingletonStorage};
 
-//TODO: set errors
-
+#[allow(clippy::too_many_arguments)]
 pub fn try_update_stake_config<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     unbond_time: Option<u64>,
     disable_treasury: bool,
     treasury: Option<HumanAddr>,
+    reward_drip_period: Option<u64>,
+    lock_tiers: Option<Vec<LockTier>>,
+    bond_limits: Option<BondLimits>,
+    fees: Option<StakeFees>,
+    instant_unbond: Option<InstantUnbondConfig>,
+    reward_vesting_period: Option<u64>,
+    unbonding_epoch: Option<u64>,
+    treasury_callback: Option<TreasuryCallback>,
 ) -> StdResult<HandleResponse> {
     let config = Config::from_storage(&mut deps.storage);
 
@@ -40,6 +64,7 @@ pub fn try_update_stake_config<S: Storage, A: Api, Q: Querier>(
     }
 
     let mut messages = vec![];
+    let previous_treasury = stake_config.treasury.clone();
 
     if disable_treasury {
         stake_config.treasury = None;
@@ -62,8 +87,92 @@ pub fn try_update_stake_config<S: Storage, A: Api, Q: Querier>(
         }
     }
 
+    let treasury_changed = stake_config.treasury != previous_treasury;
+
+    // The callback code hash belongs to the previous treasury
+    if treasury_changed {
+        TreasuryCallback::default().save(&mut deps.storage)?;
+    }
+
     stake_config.save(&mut deps.storage)?;
 
+    if let Some(reward_drip_period) = reward_drip_period {
+        // Rewards already dripping keep their current schedule
+        update_reward_drip(&mut deps.storage, env.block.time)?;
+        RewardDripPeriod(reward_drip_period).save(&mut deps.storage)?;
+    }
+
+    // Existing locks keep the bonus they were given
+    if let Some(lock_tiers) = lock_tiers {
+        LockTiers(lock_tiers).save(&mut deps.storage)?;
+    }
+
+    // Already bonded tokens are not affected by lowered limits
+    if let Some(bond_limits) = bond_limits {
+        if let Some(min_bond) = bond_limits.min_bond {
+            let caps = [
+                bond_limits.max_bond_per_account,
+                bond_limits.max_total_staked,
+            ];
+            if caps.iter().flatten().any(|max| min_bond > *max) {
+                return Err(ContractError::InvalidConfig(
+                    "Minimum bond cannot be over the bond caps",
+                )
+                .into());
+            }
+        }
+        bond_limits.save(&mut deps.storage)?;
+    }
+
+    if let Some(fees) = fees {
+        if fees.bond > FEE_PRECISION as u64 || fees.unbond > FEE_PRECISION as u64 {
+            return Err(
+                ContractError::InvalidConfig("Fees cannot be over 10000 basis points").into(),
+            );
+        }
+        fees.save(&mut deps.storage)?;
+    }
+
+    // A smaller buffer is drained by instant unbonds rather than sent to the treasury
+    if let Some(instant_unbond) = instant_unbond {
+        if instant_unbond.penalty > FEE_PRECISION as u64 {
+            return Err(
+                ContractError::InvalidConfig("Penalty cannot be over 10000 basis points").into(),
+            );
+        }
+        instant_unbond.save(&mut deps.storage)?;
+    }
+
+    // Rewards already vesting keep their current schedule
+    if let Some(reward_vesting_period) = reward_vesting_period {
+        RewardVestingPeriod(reward_vesting_period).save(&mut deps.storage)?;
+    }
+
+    if let Some(length) = unbonding_epoch {
+        if length == 0 {
+            return Err(ContractError::InvalidConfig("Unbonding epoch cannot be zero").into());
+        }
+
+        // Unbondings already made keep their epochs, so the new length only applies
+        // once the epoch of the latest release is over
+        let after = LastUnbondingRelease::load(&deps.storage)?
+            .0
+            .max(env.block.time);
+        let mut epochs = UnbondingEpochs::load(&deps.storage)?;
+        epochs.0.retain(|epoch| epoch.start <= after);
+
+        let current = epochs.0.last().map_or(DAY, |epoch| epoch.length);
+        if current != length {
+            let start = epochs.round(after) + current;
+            epochs.0.push(UnbondingEpoch { start, length });
+        }
+        epochs.save(&mut deps.storage)?;
+    }
+
+    if let Some(treasury_callback) = treasury_callback {
+        treasury_callback.save(&mut deps.storage)?;
+    }
+
     Ok(HandleResponse {
         messages,
         log: vec![],
@@ -73,7 +182,7 @@ pub fn try_update_stake_config<S: Storage, A: Api, Q: Querier>(
     })
 }
 
-const DAY: u64 = 86400; //60 * 60 * 24
+pub const DAY: u64 = 86400; //60 * 60 * 24
 
 ///
 /// Rounds down a date to the nearest day
@@ -83,188 +192,816 @@ fn round_date(date: u64) -> u64 {
 }
 
 ///
-/// Updates total states to reflect balance changes
+/// Returns the unbonding record of an epoch, epochs without unbondings are empty and funded
 ///
-fn add_balance<S: Storage>(
-    storage: &mut S,
-    stake_config: &StakeConfig,
-    sender: &HumanAddr,
-    sender_canon: &CanonicalAddr,
-    amount: u128,
-) -> StdResult<()> {
-    // Check if user account exists
-    let mut user_shares = UserShares::may_load(storage, sender.as_str().as_bytes())?
-        .unwrap_or(UserShares(Uint128::zero()));
+pub fn daily_unbonding<S: Storage>(storage: &S, epoch: u64) -> StdResult<DailyUnbonding> {
+    Ok(
+        DailyUnbondingRecord::may_load(storage, &epoch.to_be_bytes())?.map_or(
+            DailyUnbonding {
+                unbonding: Uint128::zero(),
+                funded: Uint128::zero(),
+                release: epoch,
+            },
+            |record| record.0,
+        ),
+    )
+}
 
-    // Update user staked tokens
-    let mut balances = Balances::from_storage(storage);
-    let mut account_balance = balances.balance(sender_canon);
-    if let Some(new_balance) = account_balance.checked_add(amount) {
-        account_balance = new_balance;
-    } else {
-        return Err(StdError::generic_err(
-            "This mint attempt would increase the account's balance above the supported maximum",
-        ));
+///
+/// Returns the unfunded epoch after the given one, or the oldest one when none is given
+///
+pub fn next_unfunded_day<S: Storage>(storage: &S, day: Option<u64>) -> StdResult<Option<u64>> {
+    match day {
+        Some(day) => {
+            Ok(UnfundedDay::may_load(storage, &day.to_be_bytes())?.and_then(|day| day.next))
+        }
+        None => Ok(UnfundedDays::load(storage)?.head),
     }
-    balances.set_account_balance(sender_canon, account_balance);
+}
 
-    // Get total supplied tokens
-    let mut total_shares = TotalShares::load(storage)?;
-    let total_tokens = TotalTokens::load(storage)?;
+///
+/// Returns the links of an epoch while it is listed in the unfunded days
+///
+fn listed_unfunded_day<S: Storage>(
+    storage: &S,
+    days: &UnfundedDays,
+    epoch: u64,
+) -> StdResult<Option<UnfundedDay>> {
+    Ok(UnfundedDay::may_load(storage, &epoch.to_be_bytes())?
+        // Only the oldest epoch is listed without a previous one
+        .filter(|day| day.prev.is_some() || days.head == Some(epoch)))
+}
 
-    // Update total staked
-    // We do this before reaching shares to get overflows out of the way
-    if let Some(total_staked) = total_tokens.0.u128().checked_add(amount) {
-        TotalTokens(Uint128(total_staked)).save(storage)?;
-    } else {
-        return Err(StdError::generic_err("Total staked tokens overflow"));
+///
+/// Returns the first unfunded epoch after the given time
+///
+pub fn unfunded_day_after<S: Storage>(storage: &S, time: u64) -> StdResult<Option<u64>> {
+    let days = UnfundedDays::load(storage)?;
+    if let Some(day) = listed_unfunded_day(storage, &days, time)? {
+        return Ok(day.next);
     }
-    let supply = ReadonlyConfig::from_storage(storage).total_supply();
-    Config::from_storage(storage).set_total_supply(supply + amount);
 
-    // Calculate shares per token supplied
-    let shares = Uint128(shares_per_token(
-        stake_config,
-        &amount,
-        &total_tokens.0.u128(),
-        &total_shares.0.u128(),
-    )?);
+    // Epochs funded since the time was listed are all before the oldest one left,
+    // so this only walks when the time falls between listed epochs
+    let mut day = days.head;
+    while let Some(epoch) = day {
+        if epoch > time {
+            break;
+        }
+        day = next_unfunded_day(storage, Some(epoch))?;
+    }
 
-    // Update total shares
-    if let Some(total_added_shares) = total_shares.0.u128().checked_add(shares.u128()) {
-        total_shares = TotalShares(Uint128(total_added_shares));
-    } else {
-        return Err(StdError::generic_err("Shares overflow"));
+    Ok(day)
+}
+
+///
+/// Links an epoch into the unfunded days, epochs are added at the end unless the unbond
+/// time was lowered
+///
+fn add_unfunded_day<S: Storage>(storage: &mut S, epoch: u64) -> StdResult<()> {
+    let mut days = UnfundedDays::load(storage)?;
+
+    // Walk back from the newest epoch to the one the new epoch follows
+    let mut prev = days.tail;
+    while let Some(day) = prev {
+        if day == epoch {
+            return Ok(());
+        }
+        if day < epoch {
+            break;
+        }
+        prev = UnfundedDay::load(storage, &day.to_be_bytes())?.prev;
     }
-    total_shares.save(storage)?;
 
-    // Update user's shares - this will not break as total_shares >= user_shares
-    user_shares.0 += shares;
-    user_shares.save(storage, sender.as_str().as_bytes())?;
+    let next = match prev {
+        Some(day) => {
+            let mut prev_day = UnfundedDay::load(storage, &day.to_be_bytes())?;
+            let next = prev_day.next;
+            prev_day.next = Some(epoch);
+            prev_day.save(storage, &day.to_be_bytes())?;
+            next
+        }
+        None => days.head.replace(epoch),
+    };
+    match next {
+        Some(day) => {
+            let mut next_day = UnfundedDay::load(storage, &day.to_be_bytes())?;
+            next_day.prev = Some(epoch);
+            next_day.save(storage, &day.to_be_bytes())?;
+        }
+        None => days.tail = Some(epoch),
+    }
 
-    Ok(())
+    UnfundedDay { prev, next }.save(storage, &epoch.to_be_bytes())?;
+    days.save(storage)
 }
 
 ///
-/// Removed items from internal supply
+/// Unlinks an epoch from the unfunded days, epochs that are not listed are left alone
 ///
-fn subtract_internal_supply<S: Storage>(
-    storage: &mut S,
-    total_shares: &mut TotalShares,
-    shares: u128,
-    total_tokens: &mut TotalTokens,
-    tokens: u128,
-    remove_supply: bool,
-) -> StdResult<()> {
-    // Update total shares
-    if let Some(total) = total_shares.0.u128().checked_sub(shares) {
-        TotalShares(Uint128(total)).save(storage)?;
-    } else {
-        return Err(StdError::generic_err("Insufficient shares"));
+fn remove_unfunded_day<S: Storage>(storage: &mut S, epoch: u64) -> StdResult<()> {
+    let mut days = UnfundedDays::load(storage)?;
+    let day = match listed_unfunded_day(storage, &days, epoch)? {
+        Some(day) => day,
+        None => return Ok(()),
+    };
+
+    match day.prev {
+        Some(prev) => {
+            let mut prev_day = UnfundedDay::load(storage, &prev.to_be_bytes())?;
+            prev_day.next = day.next;
+            prev_day.save(storage, &prev.to_be_bytes())?;
+        }
+        None => days.head = day.next,
+    }
+    match day.next {
+        Some(next) => {
+            let mut next_day = UnfundedDay::load(storage, &next.to_be_bytes())?;
+            next_day.prev = day.prev;
+            next_day.save(storage, &next.to_be_bytes())?;
+        }
+        None => days.tail = day.prev,
     }
 
-    // Update total staked
-    if let Some(total) = total_tokens.0.u128().checked_sub(tokens) {
-        TotalTokens(Uint128(total)).save(storage)?;
-    } else {
-        return Err(StdError::generic_err("Insufficient tokens"));
+    UnfundedDay {
+        prev: None,
+        next: None,
     }
-    if remove_supply {
-        let supply = ReadonlyConfig::from_storage(storage).total_supply();
-        if let Some(total) = supply.checked_sub(tokens) {
-            Config::from_storage(storage).set_total_supply(total);
-        } else {
-            return Err(StdError::generic_err("Insufficient shares"));
+    .save(storage, &epoch.to_be_bytes())?;
+    days.save(storage)
+}
+
+// Scales the unbonding loss factor, a factor of UNBONDING_LOSS_PRECISION means no losses
+const UNBONDING_LOSS_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+///
+/// Returns the portion of the unbondings released in that epoch left after losses
+///
+fn unbonding_loss<S: Storage>(storage: &S, release: u64) -> StdResult<u128> {
+    let epoch = UnbondingEpochs::load(storage)?.round(release);
+    Ok(UnbondingLoss::may_load(storage, &epoch.to_be_bytes())?
+        .map_or(UNBONDING_LOSS_PRECISION, |loss| loss.0.u128()))
+}
+
+///
+/// Returns the tokens a stored user unbonding is currently worth
+///
+pub fn unbonding_value<S: Storage>(storage: &S, unbonding: &Unbonding) -> StdResult<Uint128> {
+    let loss = unbonding_loss(storage, unbonding.release)?;
+    Ok(Uint128(
+        (u256::from(unbonding.amount.u128()) * u256::from(loss)
+            / u256::from(UNBONDING_LOSS_PRECISION))
+        .as_u128(),
+    ))
+}
+
+///
+/// Scales an unbonding up so previously reported losses do not apply to it,
+/// rounds up so that unbonding_value returns the original amount
+///
+fn normalize_unbonding<S: Storage>(
+    storage: &S,
+    amount: Uint128,
+    release: u64,
+) -> StdResult<Uint128> {
+    let loss = u256::from(unbonding_loss(storage, release)?);
+    let scaled = u256::from(amount.u128()) * u256::from(UNBONDING_LOSS_PRECISION);
+    Ok(Uint128(((scaled + loss - u256::ONE) / loss).as_u128()))
+}
+
+///
+/// Returns the IDs of the account's queued unbondings, every entry is given its own ID
+/// when it is queued
+///
+pub fn unbonding_ids<S: Storage>(
+    storage: &S,
+    account: &HumanAddr,
+    entries: usize,
+) -> StdResult<Vec<u64>> {
+    let ids =
+        UnbondingIds::may_load(storage, account.as_str().as_bytes())?.map_or(vec![], |ids| ids.0);
+    if ids.len() != entries {
+        return Err(StdError::generic_err(
+            "Unbonding queue entries and IDs are out of sync",
+        ));
+    }
+
+    Ok(ids)
+}
+
+///
+/// Hands out the next unbonding ID
+///
+fn next_unbonding_id<S: Storage>(storage: &mut S) -> StdResult<u64> {
+    let id = UnbondingCount::load(storage)?.0;
+    UnbondingCount(id + 1).save(storage)?;
+    Ok(id)
+}
+
+// Scales the fraction of a token kept in the rounding dust
+const DUST_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+///
+/// Returns the shares needed to back the tokens, rounded up so a claimant keeps no
+/// truncated fraction that a later claim could count again
+///
+fn backing_shares(tokens: u128, total_tokens: u128, total_shares: u128) -> u128 {
+    let total_tokens = u256::from(total_tokens);
+    ((u256::from(tokens) * u256::from(total_shares) + total_tokens - u256::ONE) / total_tokens)
+        .as_u128()
+}
+
+///
+/// Returns what the burned shares are worth beyond the paid tokens, scaled by DUST_PRECISION
+///
+fn burned_dust(shares: u128, tokens: u128, total_tokens: u128, total_shares: u128) -> u128 {
+    let value = u256::from(shares) * u256::from(total_tokens);
+    let paid = u256::from(tokens) * u256::from(total_shares);
+    if value <= paid {
+        return 0;
+    }
+
+    ((value - paid) * u256::from(DUST_PRECISION) / u256::from(total_shares)).as_u128()
+}
+
+///
+/// Records truncated fractions, whole tokens are taken out of the share price
+///
+fn add_rounding_dust<S: Storage>(storage: &mut S, truncated: u128, time: u64) -> StdResult<()> {
+    if truncated == 0 {
+        return Ok(());
+    }
+
+    let mut dust = RoundingDust::load(storage)?;
+    dust.remainder += Uint128(truncated);
+
+    let tokens = dust.remainder.u128() / DUST_PRECISION;
+    if tokens != 0 {
+        let mut total_tokens = TotalTokens::load(storage)?;
+        total_tokens.0 = (total_tokens.0 - Uint128(tokens))?;
+        total_tokens.save(storage)?;
+
+        dust.tokens += Uint128(tokens);
+        dust.remainder = Uint128(dust.remainder.u128() % DUST_PRECISION);
+        LastRateChange(time).save(storage)?;
+
+        // Dust is cut from the claimed rewards and swept out of the contract's balance
+        take_held_rewards(storage, tokens)?;
+    }
+
+    dust.save(storage)
+}
+
+// Fraction of a token a pool with stakers must hold, a donation into anything smaller
+// could price new shares far above their rounding unit
+const MINIMUM_STAKE_FRACTION: u128 = 100_000;
+
+///
+/// Smallest supply a pool with stakers can hold, scaled to the token decimals
+///
+pub fn minimum_stake(decimals: u8) -> u128 {
+    (10u128.pow(decimals.into()) / MINIMUM_STAKE_FRACTION).max(1)
+}
+
+///
+/// Checks the supply is either empty or above the minimum stake
+///
+fn check_minimum_stake<S: Storage>(storage: &S, supply: u128) -> StdResult<()> {
+    let minimum = minimum_stake(ReadonlyConfig::from_storage(storage).constants()?.decimals);
+    if supply != 0 && supply < minimum {
+        return Err(ContractError::PoolBelowMinimum {
+            minimum: Uint128(minimum),
         }
+        .into());
     }
 
     Ok(())
 }
 
 ///
-/// Updates total states to reflect balance changes
+/// Sets aside the tokens and pending rewards left behind by a pool without shares,
+/// otherwise the next staker would get them for free
 ///
-fn remove_balance<S: Storage>(
+fn sweep_empty_pool<S: Storage>(storage: &mut S) -> StdResult<()> {
+    let total_tokens = TotalTokens::load(storage)?;
+    let mut drip = RewardDrip::load(storage)?;
+
+    let leftover = total_tokens.0 + drip.remaining;
+    if leftover == Uint128::zero() {
+        return Ok(());
+    }
+
+    let mut dust = RoundingDust::load(storage)?;
+    dust.tokens += leftover;
+    dust.save(storage)?;
+
+    TotalTokens(Uint128::zero()).save(storage)?;
+    HeldRewards(Uint128::zero()).save(storage)?;
+    drip.remaining = Uint128::zero();
+    drip.save(storage)
+}
+
+// Lock tier bonuses are in basis points
+const LOCK_BONUS_PRECISION: u128 = 10_000;
+
+// Fees are set in basis points
+const FEE_PRECISION: u128 = 10_000;
+
+///
+/// Fee taken from an amount, rounded down
+///
+fn calculate_fee(amount: u128, fee: u64) -> u128 {
+    (u256::from(amount) * u256::from(fee) / u256::from(FEE_PRECISION)).as_u128()
+}
+
+pub fn user_locks<S: Storage>(storage: &S, account: &HumanAddr) -> StdResult<Vec<Lock>> {
+    Ok(UserLocks::may_load(storage, account.as_str().as_bytes())?
+        .map_or_else(Vec::new, |locks| locks.0))
+}
+
+///
+/// Returns the tokens that cannot be unbonded or transferred yet
+///
+pub fn locked_tokens<S: Storage>(storage: &S, account: &HumanAddr, time: u64) -> StdResult<u128> {
+    Ok(user_locks(storage, account)?
+        .iter()
+        .filter(|lock| lock.release > time)
+        .map(|lock| lock.amount.u128())
+        .sum())
+}
+
+///
+/// Returns the tokens backing the account's bonus shares, which are not claimable
+///
+pub fn locked_bonus<S: Storage>(storage: &S, account: &HumanAddr) -> StdResult<u128> {
+    Ok(user_locks(storage, account)?
+        .iter()
+        .map(|lock| lock.bonus.u128())
+        .sum())
+}
+
+///
+/// Burns the bonus shares of expired locks together with the tokens backing them
+///
+fn release_locks<S: Storage>(
     storage: &mut S,
     stake_config: &StakeConfig,
     account: &HumanAddr,
-    account_cannon: &CanonicalAddr,
-    amount: u128,
-    time: u64,
+    account_canon: &CanonicalAddr,
+    block: &BlockInfo,
 ) -> StdResult<()> {
-    // Return insufficient funds
-    let user_shares =
-        UserShares::may_load(storage, account.as_str().as_bytes())?.expect("No funds");
+    let (expired, active): (Vec<Lock>, Vec<Lock>) = user_locks(storage, account)?
+        .into_iter()
+        .partition(|lock| lock.release <= block.time);
+
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    let amount: u128 = expired.iter().map(|lock| lock.amount.u128()).sum();
+    let bonus: u128 = expired.iter().map(|lock| lock.bonus.u128()).sum();
+
+    let user_shares = UserShares::load(storage, account.as_str().as_bytes())?;
+    accrue_rewards(storage, account, user_shares.0.u128())?;
 
-    // Get total supplied tokens
     let mut total_shares = TotalShares::load(storage)?;
     let mut total_tokens = TotalTokens::load(storage)?;
 
-    // Calculate shares per token supplied
+    // Losses can leave the account with less than its bonus is worth, the bonus
+    // tokens never existed so they are removed in full either way
     let shares = shares_per_token(
         stake_config,
-        &amount,
+        &bonus,
         &total_tokens.0.u128(),
         &total_shares.0.u128(),
-    )?;
-
-    // Update user's shares
-    if let Some(user_shares) = user_shares.0.u128().checked_sub(shares) {
-        UserShares(Uint128(user_shares)).save(storage, account.as_str().as_bytes())?;
-    } else {
-        return Err(StdError::generic_err("Insufficient shares"));
-    }
+    )?
+    .min(user_shares.0.u128());
 
+    UserShares(Uint128(user_shares.0.u128() - shares))
+        .save(storage, account.as_str().as_bytes())?;
     subtract_internal_supply(
         storage,
         &mut total_shares,
         shares,
         &mut total_tokens,
-        amount,
-        true,
+        bonus,
+        false,
     )?;
 
-    // Load balance
-    let mut balances = Balances::from_storage(storage);
-    let mut account_balance = balances.balance(account_cannon);
-    let account_tokens = account_balance;
+    let mut total_bonus = TotalLockBonus::load(storage)?;
+    total_bonus.0 = (total_bonus.0 - Uint128(bonus))?;
+    total_bonus.save(storage)?;
+    LastRateChange(block.time).save(storage)?;
+
+    UserLocks(active).save(storage, account.as_str().as_bytes())?;
+
+    let symbol = ReadonlyConfig::from_storage(storage).constants()?.symbol;
+    store_unlock(storage, account_canon, Uint128(amount), symbol, None, block)?;
 
-    if let Some(new_balance) = account_balance.checked_sub(amount) {
-        account_balance = new_balance;
-    } else {
-        return Err(StdError::generic_err(
-            "This burn attempt would decrease the account's balance to a negative",
-        ));
-    }
-    balances.set_account_balance(account_cannon, account_balance);
-    remove_from_cooldown(
-        storage,
-        account,
-        Uint128(account_tokens),
-        Uint128(amount),
-        time,
-    )?;
     Ok(())
 }
 
-pub fn claim_rewards<S: Storage>(
+///
+/// Locks unlocked tokens of the account for one of the configured tiers
+///
+#[allow(clippy::too_many_arguments)]
+fn lock_tokens<S: Storage>(
     storage: &mut S,
     stake_config: &StakeConfig,
-    sender: &HumanAddr,
+    account: &HumanAddr,
+    account_canon: &CanonicalAddr,
+    amount: Uint128,
+    duration: u64,
+    symbol: String,
+    block: &BlockInfo,
+) -> StdResult<()> {
+    let tier = LockTiers::load(storage)?
+        .0
+        .into_iter()
+        .find(|tier| tier.duration == duration)
+        .ok_or_else(|| StdError::from(ContractError::NoLockTier))?;
+
+    release_locks(storage, stake_config, account, account_canon, block)?;
+
+    let balance = Balances::from_storage(storage).balance(account_canon);
+    let locked = locked_tokens(storage, account, block.time)?;
+    if amount.u128() > balance.saturating_sub(locked) {
+        return Err(ContractError::InsufficientUnlockedTokens.into());
+    }
+
+    // The bonus shares are backed by tokens that only exist while the lock does,
+    // so they earn rewards without diluting the other stakers' balances
+    let bonus = (u256::from(amount.u128()) * u256::from(tier.bonus)
+        / u256::from(LOCK_BONUS_PRECISION))
+    .as_u128();
+
+    let mut user_shares = UserShares::load(storage, account.as_str().as_bytes())?;
+    accrue_rewards(storage, account, user_shares.0.u128())?;
+
+    let total_shares = TotalShares::load(storage)?;
+    let total_tokens = TotalTokens::load(storage)?;
+
+    let bonus_shares = shares_per_token(
+        stake_config,
+        &bonus,
+        &total_tokens.0.u128(),
+        &total_shares.0.u128(),
+    )?;
+
+    if let Some(total) = total_tokens.0.u128().checked_add(bonus) {
+        TotalTokens(Uint128(total)).save(storage)?;
+    } else {
+        return Err(ContractError::Overflow("Total staked tokens overflow").into());
+    }
+    if let Some(total) = total_shares.0.u128().checked_add(bonus_shares) {
+        TotalShares(Uint128(total)).save(storage)?;
+    } else {
+        return Err(ContractError::Overflow("Shares overflow").into());
+    }
+    user_shares.0 += Uint128(bonus_shares);
+    user_shares.save(storage, account.as_str().as_bytes())?;
+
+    let mut total_bonus = TotalLockBonus::load(storage)?;
+    total_bonus.0 += Uint128(bonus);
+    total_bonus.save(storage)?;
+
+    let mut locks = user_locks(storage, account)?;
+    locks.push(Lock {
+        amount,
+        bonus: Uint128(bonus),
+        release: block.time + duration,
+    });
+    UserLocks(locks).save(storage, account.as_str().as_bytes())?;
+
+    store_lock(storage, account_canon, amount, symbol, None, block)
+}
+
+///
+/// Releases the dripped portion of received rewards into the total tokens
+///
+pub fn update_reward_drip<S: Storage>(storage: &mut S, time: u64) -> StdResult<()> {
+    let mut drip = RewardDrip::load(storage)?;
+
+    let released = drip.released(time);
+    if released != 0 {
+        let total_tokens = TotalTokens::load(storage)?;
+        if let Some(total) = total_tokens.0.u128().checked_add(released) {
+            TotalTokens(Uint128(total)).save(storage)?;
+        } else {
+            return Err(ContractError::Overflow("Total staked tokens overflow").into());
+        }
+        drip.remaining = Uint128(drip.remaining.u128() - released);
+        LastRateChange(time).save(storage)?;
+    }
+
+    if time > drip.last_update {
+        drip.last_update = time;
+        drip.save(storage)?;
+    }
+
+    snapshot_stake_rate(storage, time)
+}
+
+///
+/// Records the share price the first time it's touched on a new day
+///
+fn snapshot_stake_rate<S: Storage>(storage: &mut S, time: u64) -> StdResult<()> {
+    let day = round_date(time);
+    let count = RateSnapshotCount::load(storage)?.0;
+
+    if count != 0 && RateSnapshot::load(storage, &(count - 1).to_be_bytes())?.day >= day {
+        return Ok(());
+    }
+
+    RateSnapshot {
+        day,
+        total_tokens: TotalTokens::load(storage)?.0,
+        total_shares: TotalShares::load(storage)?.0,
+    }
+    .save(storage, &count.to_be_bytes())?;
+    RateSnapshotCount(count + 1).save(storage)
+}
+
+///
+/// Adds the account to the holder registry if it is not in it yet
+///
+pub fn register_holder<S: Storage>(storage: &mut S, account: &HumanAddr) -> StdResult<()> {
+    let key = account.as_str().as_bytes();
+    if HolderIndex::may_load(storage, key)?.is_some() {
+        return Ok(());
+    }
+
+    let count = HolderCount::load(storage)?.0;
+    Holder(account.clone()).save(storage, &count.to_be_bytes())?;
+    HolderIndex(count).save(storage, key)?;
+    HolderCount(count + 1).save(storage)
+}
+
+///
+/// Returns how much more the account can bond under the bond caps
+///
+fn bond_room<S: Storage>(storage: &S, account_canon: &CanonicalAddr) -> StdResult<u128> {
+    let limits = BondLimits::load(storage)?;
+
+    let mut room = u128::MAX;
+
+    if let Some(max_bond) = limits.max_bond_per_account {
+        let bonded = ReadonlyBalances::from_storage(storage).account_amount(account_canon);
+        room = room.min(max_bond.u128().saturating_sub(bonded));
+    }
+
+    if let Some(max_staked) = limits.max_total_staked {
+        let staked = ReadonlyConfig::from_storage(storage).total_supply();
+        room = room.min(max_staked.u128().saturating_sub(staked));
+    }
+
+    Ok(room)
+}
+
+///
+/// Returns how much of a bond fits under the bond limits, nothing fits below the minimum bond
+///
+fn bond_allowance<S: Storage>(
+    storage: &S,
+    account_canon: &CanonicalAddr,
+    amount: u128,
+) -> StdResult<u128> {
+    if let Some(min_bond) = BondLimits::load(storage)?.min_bond {
+        if amount < min_bond.u128() {
+            return Ok(0);
+        }
+    }
+
+    Ok(amount.min(bond_room(storage, account_canon)?))
+}
+
+///
+/// Updates total states to reflect balance changes
+///
+fn add_balance<S: Storage>(
+    storage: &mut S,
+    stake_config: &StakeConfig,
+    sender: &HumanAddr,
+    sender_canon: &CanonicalAddr,
+    amount: u128,
+    time: u64,
+) -> StdResult<()> {
+    update_reward_drip(storage, time)?;
+    register_holder(storage, sender)?;
+
+    if TotalShares::load(storage)?.0 == Uint128::zero() {
+        sweep_empty_pool(storage)?;
+    }
+
+    // Check if user account exists
+    let mut user_shares = UserShares::may_load(storage, sender.as_str().as_bytes())?
+        .unwrap_or(UserShares(Uint128::zero()));
+
+    // Settle reward tokens before the user's shares change
+    accrue_rewards(storage, sender, user_shares.0.u128())?;
+
+    // Update user staked tokens
+    let mut balances = Balances::from_storage(storage);
+    let mut account_balance = balances.balance(sender_canon);
+    if let Some(new_balance) = account_balance.checked_add(amount) {
+        account_balance = new_balance;
+    } else {
+        return Err(ContractError::BalanceOverflow.into());
+    }
+    balances.set_account_balance(sender_canon, account_balance);
+
+    // Get total supplied tokens
+    let mut total_shares = TotalShares::load(storage)?;
+    let total_tokens = TotalTokens::load(storage)?;
+
+    // Update total staked
+    // We do this before reaching shares to get overflows out of the way
+    if let Some(total_staked) = total_tokens.0.u128().checked_add(amount) {
+        TotalTokens(Uint128(total_staked)).save(storage)?;
+    } else {
+        return Err(ContractError::Overflow("Total staked tokens overflow").into());
+    }
+    let supply = ReadonlyConfig::from_storage(storage).total_supply() + amount;
+    check_minimum_stake(storage, supply)?;
+    Config::from_storage(storage).set_total_supply(supply);
+
+    // Calculate shares per token supplied
+    let shares = Uint128(shares_per_token(
+        stake_config,
+        &amount,
+        &total_tokens.0.u128(),
+        &total_shares.0.u128(),
+    )?);
+
+    // Update total shares
+    if let Some(total_added_shares) = total_shares.0.u128().checked_add(shares.u128()) {
+        total_shares = TotalShares(Uint128(total_added_shares));
+    } else {
+        return Err(ContractError::Overflow("Shares overflow").into());
+    }
+    total_shares.save(storage)?;
+
+    // Update user's shares - this will not break as total_shares >= user_shares
+    user_shares.0 += shares;
+    user_shares.save(storage, sender.as_str().as_bytes())?;
+
+    Ok(())
+}
+
+///
+/// Removed items from internal supply
+///
+fn subtract_internal_supply<S: Storage>(
+    storage: &mut S,
+    total_shares: &mut TotalShares,
+    shares: u128,
+    total_tokens: &mut TotalTokens,
+    tokens: u128,
+    remove_supply: bool,
+) -> StdResult<()> {
+    // Update total shares
+    if let Some(total) = total_shares.0.u128().checked_sub(shares) {
+        TotalShares(Uint128(total)).save(storage)?;
+    } else {
+        return Err(ContractError::InsufficientShares.into());
+    }
+
+    // Update total staked
+    if let Some(total) = total_tokens.0.u128().checked_sub(tokens) {
+        TotalTokens(Uint128(total)).save(storage)?;
+    } else {
+        return Err(ContractError::InsufficientTokens.into());
+    }
+    if remove_supply {
+        let supply = ReadonlyConfig::from_storage(storage).total_supply();
+        if let Some(total) = supply.checked_sub(tokens) {
+            Config::from_storage(storage).set_total_supply(total);
+        } else {
+            return Err(ContractError::InsufficientShares.into());
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Updates total states to reflect balance changes
+///
+fn remove_balance<S: Storage>(
+    storage: &mut S,
+    stake_config: &StakeConfig,
+    account: &HumanAddr,
+    account_cannon: &CanonicalAddr,
+    amount: u128,
+    time: u64,
+) -> StdResult<()> {
+    update_reward_drip(storage, time)?;
+
+    let balance = Balances::from_storage(storage).balance(account_cannon);
+    if amount > balance.saturating_sub(locked_tokens(storage, account, time)?) {
+        return Err(ContractError::TokensLocked.into());
+    }
+
+    // Return insufficient funds
+    let user_shares = UserShares::may_load(storage, account.as_str().as_bytes())?
+        .ok_or_else(|| StdError::from(ContractError::NoFunds))?;
+
+    accrue_rewards(storage, account, user_shares.0.u128())?;
+
+    // Get total supplied tokens
+    let mut total_shares = TotalShares::load(storage)?;
+    let mut total_tokens = TotalTokens::load(storage)?;
+
+    // Calculate shares per token supplied
+    let shares = shares_per_token(
+        stake_config,
+        &amount,
+        &total_tokens.0.u128(),
+        &total_shares.0.u128(),
+    )?;
+
+    // Update user's shares
+    if let Some(user_shares) = user_shares.0.u128().checked_sub(shares) {
+        UserShares(Uint128(user_shares)).save(storage, account.as_str().as_bytes())?;
+    } else {
+        return Err(ContractError::InsufficientShares.into());
+    }
+
+    subtract_internal_supply(
+        storage,
+        &mut total_shares,
+        shares,
+        &mut total_tokens,
+        amount,
+        true,
+    )?;
+    check_minimum_stake(
+        storage,
+        ReadonlyConfig::from_storage(storage).total_supply(),
+    )?;
+
+    // Load balance
+    let mut balances = Balances::from_storage(storage);
+    let mut account_balance = balances.balance(account_cannon);
+    let account_tokens = account_balance;
+
+    if let Some(new_balance) = account_balance.checked_sub(amount) {
+        account_balance = new_balance;
+    } else {
+        return Err(ContractError::BalanceUnderflow.into());
+    }
+    balances.set_account_balance(account_cannon, account_balance);
+    remove_from_cooldown(
+        storage,
+        account,
+        Uint128(account_tokens),
+        Uint128(amount),
+        time,
+    )?;
+    Ok(())
+}
+
+pub fn claim_rewards<S: Storage>(
+    storage: &mut S,
+    stake_config: &StakeConfig,
+    sender: &HumanAddr,
     sender_canon: &CanonicalAddr,
+    block: &BlockInfo,
 ) -> StdResult<u128> {
-    let user_shares =
-        UserShares::may_load(storage, sender.as_str().as_bytes())?.expect("No funds");
+    update_reward_drip(storage, block.time)?;
+    release_locks(storage, stake_config, sender, sender_canon, block)?;
+
+    let user_shares = UserShares::may_load(storage, sender.as_str().as_bytes())?
+        .ok_or_else(|| StdError::from(ContractError::NoFunds))?;
+
+    accrue_rewards(storage, sender, user_shares.0.u128())?;
 
     let user_balance = Balances::from_storage(storage).balance(sender_canon);
+    let bonus = locked_bonus(storage, sender)?;
 
     // Get total supplied tokens
     let mut total_shares = TotalShares::load(storage)?;
     let mut total_tokens = TotalTokens::load(storage)?;
 
-    let (reward_token, reward_shares) = calculate_rewards(
+    // Realize reported losses by writing the balance down to what its shares are worth
+    let user_value = tokens_per_share(
+        stake_config,
+        &user_shares.0.u128(),
+        &total_tokens.0.u128(),
+        &total_shares.0.u128(),
+    )?;
+    if user_value < user_balance + bonus {
+        let written_down = user_value.saturating_sub(bonus);
+        Balances::from_storage(storage).set_account_balance(sender_canon, written_down);
+        let supply = ReadonlyConfig::from_storage(storage).total_supply();
+        Config::from_storage(storage)
+            .set_total_supply(supply.saturating_sub(user_balance - written_down));
+        return Ok(0);
+    }
+
+    let (reward_token, _) = calculate_rewards(
         stake_config,
-        user_balance,
+        user_balance + bonus,
         user_shares.0.u128(),
         total_tokens.0.u128(),
         total_shares.0.u128(),
@@ -275,10 +1012,26 @@ pub fn claim_rewards<S: Storage>(
         return Ok(reward_token);
     }
 
+    // Everything above the shares backing the balance is burned, the part of it
+    // worth more than the reward is the truncated fraction
+    let reward_shares = user_shares.0.u128()
+        - backing_shares(
+            user_balance + bonus,
+            total_tokens.0.u128(),
+            total_shares.0.u128(),
+        )
+        .min(user_shares.0.u128());
+    let truncated = burned_dust(
+        reward_shares,
+        reward_token,
+        total_tokens.0.u128(),
+        total_shares.0.u128(),
+    );
+
     if let Some(user_shares) = user_shares.0.u128().checked_sub(reward_shares) {
         UserShares(Uint128(user_shares)).save(storage, sender.as_str().as_bytes())?;
     } else {
-        return Err(StdError::generic_err("Insufficient shares"));
+        return Err(ContractError::InsufficientShares.into());
     }
 
     subtract_internal_supply(
@@ -290,9 +1043,21 @@ pub fn claim_rewards<S: Storage>(
         false,
     )?;
 
+    // Claimed rewards leave the contract or are bonded again at the treasury
+    take_held_rewards(storage, reward_token)?;
+    add_rounding_dust(storage, truncated, block.time)?;
+
     Ok(reward_token)
 }
 
+///
+/// Removes tokens from the held rewards, rewards received before they were tracked are not counted
+///
+fn take_held_rewards<S: Storage>(storage: &mut S, amount: u128) -> StdResult<()> {
+    let held = HeldRewards::load(storage)?.0.u128();
+    HeldRewards(Uint128(held.saturating_sub(amount))).save(storage)
+}
+
 pub fn shares_per_token(
     config: &StakeConfig,
     token_amount: &u128,
@@ -303,20 +1068,29 @@ pub fn shares_per_token(
     let t_shares = u256::from(*total_shares);
     let tokens = u256::from(*token_amount);
 
-    if *total_tokens == 0 && *total_shares == 0 {
+    // Tokens left behind by a pool with no shares are swept before anyone bonds into it
+    if *total_shares == 0 {
         // Used to normalize the staked token to the stake token
         let token_multiplier = u256::from(10u16).pow(config.decimal_difference.into());
         if let Some(shares) = tokens.checked_mul(token_multiplier) {
             return Ok(shares.as_u128());
         } else {
-            return Err(StdError::generic_err("Share calculation overflow"));
+            return Err(ContractError::Overflow("Share calculation overflow").into());
         }
     }
 
-    if let Some(shares) = tokens.checked_mul(t_shares) {
-        return Ok((shares / t_tokens).as_u128());
+    // Shares left without tokens can't be priced, only nothing can be converted
+    if *total_tokens == 0 {
+        if *token_amount == 0 {
+            return Ok(0);
+        }
+        return Err(ContractError::UnbackedShares.into());
+    }
+
+    if let Some(shares) = tokens.checked_mul(t_shares) {
+        return Ok((shares / t_tokens).as_u128());
     } else {
-        return Err(StdError::generic_err("Share calculation overflow"));
+        return Err(ContractError::Overflow("Share calculation overflow").into());
     }
 }
 
@@ -330,20 +1104,20 @@ pub fn tokens_per_share(
     let t_shares = u256::from(*total_shares);
     let shares = u256::from(*shares_amount);
 
-    if *total_tokens == 0 && *total_shares == 0 {
+    if *total_shares == 0 {
         // Used to normalize the staked token to the stake tokes
         let token_multiplier = u256::from(10u16).pow(config.decimal_difference.into());
         if let Some(tokens) = shares.checked_div(token_multiplier) {
             return Ok(tokens.as_u128());
         } else {
-            return Err(StdError::generic_err("Token calculation overflow"));
+            return Err(ContractError::Overflow("Token calculation overflow").into());
         }
     }
 
     if let Some(tokens) = shares.checked_mul(t_tokens) {
         return Ok((tokens / t_shares).as_u128());
     } else {
-        return Err(StdError::generic_err("Token calculation overflow"));
+        return Err(ContractError::Overflow("Token calculation overflow").into());
     }
 }
 
@@ -357,7 +1131,9 @@ pub fn calculate_rewards(
     total_tokens: u128,
     total_shares: u128,
 ) -> StdResult<(u128, u128)> {
-    let token_reward = tokens_per_share(config, &shares, &total_tokens, &total_shares)? - tokens;
+    // Shares can be worth less than the staked tokens after a reported loss
+    let token_reward =
+        tokens_per_share(config, &shares, &total_tokens, &total_shares)?.saturating_sub(tokens);
     Ok((
         token_reward,
         shares_per_token(config, &token_reward, &total_tokens, &total_shares)?,
@@ -378,14 +1154,33 @@ pub fn try_receive<S: Storage, A: Api, Q: Querier>(
     let stake_config = StakeConfig::load(&deps.storage)?;
 
     if env.message.sender != stake_config.staked_token.address {
-        return Err(StdError::generic_err("Not the stake token"));
+        return match is_reward_token(&deps.storage, &env.message.sender)? {
+            Some(token) => {
+                try_receive_reward_token(deps, env, token, sender_canon, amount, msg, memo)
+            }
+            None => Err(ContractError::NotStakeToken.into()),
+        };
     }
 
     let receive_type: ReceiveType;
+    let mut lock = None;
     if let Some(msg) = msg {
-        receive_type = from_binary(&msg)?;
+        receive_type = match from_binary(&msg) {
+            Ok(receive_type) => receive_type,
+            // Receive types only this contract understands
+            Err(err) => match from_binary(&msg) {
+                Ok(StakeReceiveType::BatchBond { bonds }) => {
+                    return try_batch_bond(deps, env, sender, amount, bonds, memo);
+                }
+                Ok(StakeReceiveType::BondAndLock { use_from, duration }) => {
+                    lock = Some(duration);
+                    ReceiveType::Bond { useFrom: use_from }
+                }
+                Err(_) => return Err(err),
+            },
+        };
     } else {
-        return Err(StdError::generic_err("No receive type supplied in message"));
+        return Err(ContractError::NoReceiveType.into());
     }
 
     let symbol = ReadonlyConfig::from_storage(&deps.storage)
@@ -394,7 +1189,7 @@ pub fn try_receive<S: Storage, A: Api, Q: Querier>(
     let mut messages = vec![];
     match receive_type {
         ReceiveType::Bond { useFrom } => {
-            let mut target = sender;
+            let mut target = sender.clone();
             let mut target_canon = sender_canon;
             if let Some(use_from) = useFrom {
                 if use_from {
@@ -403,30 +1198,96 @@ pub fn try_receive<S: Storage, A: Api, Q: Querier>(
                 }
             }
 
-            // Update user stake
-            add_balance(
+            let (bonded, fee) = bond_tokens(
                 &mut deps.storage,
                 &stake_config,
                 &target,
                 &target_canon,
-                amount.u128(),
+                amount,
+                symbol,
+                memo,
+                lock,
+                &env.block,
+            )?;
+
+            // Send tokens
+            let sent =
+                forward_staked_tokens(&mut deps.storage, &mut messages, &stake_config, bonded)?;
+            notify_bonded(
+                &deps.storage,
+                &mut messages,
+                &stake_config,
+                Uint128(sent.u128().saturating_sub(fee.u128())),
             )?;
 
+            refund_deposit(&mut messages, &stake_config, sender, (amount - bonded)?)?;
+        }
+
+        ReceiveType::Reward => {
+            // Rewards sent to a pool below the minimum would let its stakers inflate the share price
+            let config = ReadonlyConfig::from_storage(&deps.storage);
+            if config.total_supply() < minimum_stake(config.constants()?.decimals) {
+                return Err(ContractError::NoStakers.into());
+            }
+
+            update_reward_drip(&mut deps.storage, env.block.time)?;
+
+            // Rewards stay in the contract to pay out claims
+            let mut held = HeldRewards::load(&deps.storage)?;
+            held.0 += amount;
+            held.save(&mut deps.storage)?;
+
+            let drip_period = RewardDripPeriod::load(&deps.storage)?.0;
+            if drip_period == 0 {
+                let mut total_tokens = TotalTokens::load(&deps.storage)?;
+                total_tokens.0 += amount;
+                total_tokens.save(&mut deps.storage)?;
+                LastRateChange(env.block.time).save(&mut deps.storage)?;
+            } else {
+                // Whatever is left gets spread together with the new reward
+                let mut drip = RewardDrip::load(&deps.storage)?;
+                drip.remaining += amount;
+                drip.last_update = env.block.time;
+                drip.end = env.block.time + drip_period;
+                drip.save(&mut deps.storage)?;
+            }
+
             // Store data
-            store_stake(
+            store_add_reward(
                 &mut deps.storage,
-                &target_canon,
+                &sender_canon,
                 amount,
                 symbol,
                 memo,
                 &env.block,
             )?;
+        }
 
-            // Send tokens
-            if let Some(treasury) = stake_config.treasury {
+        ReceiveType::Unbond => {
+            let mut remaining_amount = amount;
+
+            // Fund the oldest days first
+            while let Some(day) = next_unfunded_day(&deps.storage, None)? {
+                if remaining_amount == Uint128::zero() {
+                    break;
+                }
+
+                let mut daily = daily_unbonding(&deps.storage, day)?;
+                remaining_amount = daily.fund(remaining_amount);
+                let is_funded = daily.is_funded();
+                DailyUnbondingRecord(daily).save(&mut deps.storage, &day.to_be_bytes())?;
+
+                if !is_funded {
+                    break;
+                }
+                remove_unfunded_day(&mut deps.storage, day)?;
+            }
+
+            // Send back if overfunded
+            if remaining_amount > Uint128::zero() {
                 messages.push(send_msg(
-                    treasury,
-                    amount,
+                    sender,
+                    remaining_amount,
                     None,
                     None,
                     None,
@@ -434,75 +1295,778 @@ pub fn try_receive<S: Storage, A: Api, Q: Querier>(
                     stake_config.staked_token.code_hash,
                     stake_config.staked_token.address,
                 )?);
-            } else {
-                let mut stored_tokens = UnsentStakedTokens::load(&deps.storage)?;
-                stored_tokens.0 += amount;
-                stored_tokens.save(&mut deps.storage)?;
             }
+
+            store_fund_unbond(
+                &mut deps.storage,
+                &sender_canon,
+                (amount - remaining_amount)?,
+                symbol,
+                None,
+                &env.block,
+            )?;
+        }
+    };
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Receive { status: Success })?),
+    })
+}
+
+///
+/// Stakes as much of the amount as the bond limits allow, returns the amount taken
+/// and the fee kept out of it
+/// The staked tokens are locked right away when a lock duration is given
+///
+#[allow(clippy::too_many_arguments)]
+fn bond_tokens<S: Storage>(
+    storage: &mut S,
+    stake_config: &StakeConfig,
+    account: &HumanAddr,
+    account_canon: &CanonicalAddr,
+    amount: Uint128,
+    symbol: String,
+    memo: Option<String>,
+    lock: Option<u64>,
+    block: &BlockInfo,
+) -> StdResult<(Uint128, Uint128)> {
+    // Deposits below the minimum are rejected instead of sent back
+    if let Some(min_bond) = BondLimits::load(storage)?.min_bond {
+        if amount < min_bond {
+            return Err(ContractError::BondBelowMinimum { min_bond }.into());
+        }
+    }
+
+    let bonded = Uint128(bond_allowance(storage, account_canon, amount.u128())?);
+    if bonded == Uint128::zero() {
+        return Ok((bonded, Uint128::zero()));
+    }
+
+    // The fee is forwarded with the stake but never credited
+    let fee = Uint128(calculate_fee(bonded.u128(), StakeFees::load(storage)?.bond));
+    let staked = (bonded - fee)?;
+
+    // Update user stake
+    add_balance(
+        storage,
+        stake_config,
+        account,
+        account_canon,
+        staked.u128(),
+        block.time,
+    )?;
+
+    // Store data
+    store_stake(storage, account_canon, staked, symbol.clone(), memo, block)?;
+
+    if fee != Uint128::zero() {
+        store_fee(storage, account_canon, fee, symbol.clone(), None, block)?;
+    }
+
+    if let Some(duration) = lock {
+        lock_tokens(
+            storage,
+            stake_config,
+            account,
+            account_canon,
+            staked,
+            duration,
+            symbol,
+            block,
+        )?;
+    }
+
+    Ok((bonded, fee))
+}
+
+///
+/// Sends back whatever went over the bond limits
+///
+fn refund_deposit(
+    messages: &mut Vec<CosmosMsg>,
+    stake_config: &StakeConfig,
+    sender: HumanAddr,
+    refund: Uint128,
+) -> StdResult<()> {
+    if refund != Uint128::zero() {
+        messages.push(send_msg(
+            sender,
+            refund,
+            None,
+            None,
+            None,
+            256,
+            stake_config.staked_token.code_hash.clone(),
+            stake_config.staked_token.address.clone(),
+        )?);
+    }
+
+    Ok(())
+}
+
+///
+/// Splits a received deposit between several stakers
+///
+fn try_batch_bond<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    bonds: Vec<BatchBondAction>,
+    memo: Option<String>,
+) -> StdResult<HandleResponse> {
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let symbol = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .symbol;
+
+    let mut total: u128 = 0;
+    for bond in bonds.iter() {
+        if let Some(new_total) = total.checked_add(bond.amount.u128()) {
+            total = new_total;
+        } else {
+            return Err(ContractError::Overflow("Bond amounts overflow").into());
+        }
+    }
+    if total != amount.u128() {
+        return Err(ContractError::BatchBondMismatch.into());
+    }
+
+    // The whole batch is rejected, so name the recipient holding it up
+    if let Some(min_bond) = BondLimits::load(&deps.storage)?.min_bond {
+        if let Some(bond) = bonds.iter().find(|bond| bond.amount < min_bond) {
+            return Err(ContractError::BatchBondBelowMinimum {
+                recipient: bond.recipient.clone(),
+                min_bond,
+            }
+            .into());
         }
+    }
+
+    let mut bonded = Uint128::zero();
+    let mut fees = Uint128::zero();
+    for bond in bonds {
+        let recipient_canon = deps.api.canonical_address(&bond.recipient)?;
+        let (taken, fee) = bond_tokens(
+            &mut deps.storage,
+            &stake_config,
+            &bond.recipient,
+            &recipient_canon,
+            bond.amount,
+            symbol.clone(),
+            memo.clone(),
+            None,
+            &env.block,
+        )?;
+        bonded += taken;
+        fees += fee;
+    }
+
+    let mut messages = vec![];
+
+    // Send tokens
+    let sent = forward_staked_tokens(&mut deps.storage, &mut messages, &stake_config, bonded)?;
+    notify_bonded(
+        &deps.storage,
+        &mut messages,
+        &stake_config,
+        Uint128(sent.u128().saturating_sub(fees.u128())),
+    )?;
+
+    refund_deposit(&mut messages, &stake_config, sender, (amount - bonded)?)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Receive { status: Success })?),
+    })
+}
+
+///
+/// Distributes a whitelisted reward token to the current stakers
+///
+fn try_receive_reward_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: Contract,
+    sender_canon: CanonicalAddr,
+    amount: Uint128,
+    msg: Option<Binary>,
+    memo: Option<String>,
+) -> StdResult<HandleResponse> {
+    let receive_type: ReceiveType;
+    if let Some(msg) = msg {
+        receive_type = from_binary(&msg)?;
+    } else {
+        return Err(ContractError::NoReceiveType.into());
+    }
 
+    match receive_type {
         ReceiveType::Reward => {
-            let mut total_tokens = TotalTokens::load(&deps.storage)?;
-            total_tokens.0 += amount;
-            total_tokens.save(&mut deps.storage)?;
+            add_token_reward(&mut deps.storage, &token.address, amount)?;
 
-            // Store data
             store_add_reward(
                 &mut deps.storage,
                 &sender_canon,
                 amount,
-                symbol,
+                token.address.to_string(),
                 memo,
                 &env.block,
             )?;
         }
+        _ => return Err(ContractError::NotReward.into()),
+    }
 
-        ReceiveType::Unbond => {
-            let mut remaining_amount = amount;
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Receive { status: Success })?),
+    })
+}
+
+///
+/// Sends newly staked tokens to the treasury or holds them until one is set,
+/// keeping back whatever the liquidity buffer is missing, returns what was sent
+///
+fn forward_staked_tokens<S: Storage>(
+    storage: &mut S,
+    messages: &mut Vec<CosmosMsg>,
+    stake_config: &StakeConfig,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let mut buffer = LiquidityBuffer::load(storage)?;
+    let target = InstantUnbondConfig::load(storage)?.buffer;
+    let kept = Uint128(
+        amount
+            .u128()
+            .min(target.u128().saturating_sub(buffer.0.u128())),
+    );
+    if kept != Uint128::zero() {
+        buffer.0 += kept;
+        buffer.save(storage)?;
+    }
+
+    let amount = (amount - kept)?;
+    if amount == Uint128::zero() {
+        return Ok(amount);
+    }
+
+    if let Some(treasury) = &stake_config.treasury {
+        messages.push(send_msg(
+            treasury.clone(),
+            amount,
+            None,
+            None,
+            None,
+            256,
+            stake_config.staked_token.code_hash.clone(),
+            stake_config.staked_token.address.clone(),
+        )?);
+    } else {
+        let mut stored_tokens = UnsentStakedTokens::load(storage)?;
+        stored_tokens.0 += amount;
+        stored_tokens.save(storage)?;
+        return Ok(Uint128::zero());
+    }
+
+    Ok(amount)
+}
+
+///
+/// Tells the treasury about staked tokens it now backs, nothing is sent for an empty amount
+///
+fn notify_bonded<S: Storage>(
+    storage: &S,
+    messages: &mut Vec<CosmosMsg>,
+    stake_config: &StakeConfig,
+    amount: Uint128,
+) -> StdResult<()> {
+    if amount == Uint128::zero() {
+        return Ok(());
+    }
+
+    notify_treasury(
+        storage,
+        messages,
+        stake_config,
+        TreasuryHandleMsg::Bonded { amount },
+    )
+}
+
+pub fn is_auto_compounding<S: Storage>(storage: &S, account: &HumanAddr) -> StdResult<bool> {
+    let auto_compound = AutoCompound::may_load(storage, account.as_str().as_bytes())?;
+    Ok(auto_compound.map_or(false, |auto_compound| auto_compound.0))
+}
+
+///
+/// Pays out rewards claimed as a side effect of another action,
+/// restaking them instead if the account has auto compounding enabled
+///
+#[allow(clippy::too_many_arguments)]
+pub fn payout_implicit_claim<S: Storage>(
+    storage: &mut S,
+    messages: &mut Vec<CosmosMsg>,
+    stake_config: &StakeConfig,
+    account: &HumanAddr,
+    account_canon: &CanonicalAddr,
+    claim: u128,
+    symbol: String,
+    block: &BlockInfo,
+) -> StdResult<()> {
+    if claim == 0 {
+        return Ok(());
+    }
+
+    // Compounding is a bond like any other, whatever doesn't fit is paid out
+    let compounded = if is_auto_compounding(storage, account)? {
+        bond_allowance(storage, account_canon, claim)?
+    } else {
+        0
+    };
+
+    if compounded != 0 {
+        add_balance(
+            storage,
+            stake_config,
+            account,
+            account_canon,
+            compounded,
+            block.time,
+        )?;
+        let sent = forward_staked_tokens(storage, messages, stake_config, Uint128(compounded))?;
+        notify_bonded(storage, messages, stake_config, sent)?;
+        store_compound(
+            storage,
+            account_canon,
+            Uint128(compounded),
+            symbol.clone(),
+            None,
+            block,
+        )?;
+    }
+
+    if claim != compounded {
+        pay_rewards(
+            storage,
+            messages,
+            stake_config,
+            account,
+            &Payout::to(account),
+            claim - compounded,
+            block.time,
+        )?;
+        store_claim_reward(
+            storage,
+            account_canon,
+            None,
+            Uint128(claim - compounded),
+            symbol,
+            None,
+            block,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Snip20 send with the recipient code hash, which the snip20 helpers do not support
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Snip20SendMsg {
+    Send {
+        recipient: HumanAddr,
+        recipient_code_hash: Option<String>,
+        amount: Uint128,
+        msg: Option<Binary>,
+        memo: Option<String>,
+        padding: Option<String>,
+    },
+}
+
+impl HandleCallback for Snip20SendMsg {
+    const BLOCK_SIZE: usize = 256;
+}
+
+///
+/// Where claimed tokens are sent, a contract recipient gets the msg along with them
+///
+pub struct Payout {
+    pub recipient: HumanAddr,
+    pub recipient_code_hash: Option<String>,
+    pub msg: Option<Binary>,
+}
+
+impl Payout {
+    pub fn new(
+        owner: &HumanAddr,
+        recipient: Option<HumanAddr>,
+        recipient_code_hash: Option<String>,
+        msg: Option<Binary>,
+    ) -> Self {
+        Self {
+            recipient: recipient.unwrap_or_else(|| owner.clone()),
+            recipient_code_hash,
+            msg,
+        }
+    }
+
+    pub fn to(owner: &HumanAddr) -> Self {
+        Self::new(owner, None, None, None)
+    }
+
+    ///
+    /// Recipient to record in the owner's history, none when it's the owner
+    ///
+    fn history_recipient<A: Api>(
+        &self,
+        api: &A,
+        owner: &HumanAddr,
+    ) -> StdResult<Option<CanonicalAddr>> {
+        if &self.recipient == owner {
+            return Ok(None);
+        }
+        Ok(Some(api.canonical_address(&self.recipient)?))
+    }
+
+    ///
+    /// Whether the payout is a plain transfer to the owner
+    ///
+    fn is_owner(&self, owner: &HumanAddr) -> bool {
+        &self.recipient == owner && self.recipient_code_hash.is_none() && self.msg.is_none()
+    }
+
+    fn cosmos_msg(&self, token: &Contract, amount: Uint128) -> StdResult<CosmosMsg> {
+        match &self.recipient_code_hash {
+            Some(code_hash) => Snip20SendMsg::Send {
+                recipient: self.recipient.clone(),
+                recipient_code_hash: Some(code_hash.clone()),
+                amount,
+                msg: self.msg.clone(),
+                memo: None,
+                padding: None,
+            }
+            .to_cosmos_msg(token.code_hash.clone(), token.address.clone(), None),
+            None => send_msg(
+                self.recipient.clone(),
+                amount,
+                self.msg.clone(),
+                None,
+                None,
+                256,
+                token.code_hash.clone(),
+                token.address.clone(),
+            ),
+        }
+    }
+}
+
+///
+/// Sends claimed rewards or adds them to the account's vesting ledger
+///
+fn pay_rewards<S: Storage>(
+    storage: &mut S,
+    messages: &mut Vec<CosmosMsg>,
+    stake_config: &StakeConfig,
+    account: &HumanAddr,
+    payout: &Payout,
+    claim: u128,
+    time: u64,
+) -> StdResult<()> {
+    let vesting_period = RewardVestingPeriod::load(storage)?.0;
+
+    if vesting_period == 0 {
+        messages.push(payout.cosmos_msg(&stake_config.staked_token, Uint128(claim))?);
+    } else {
+        let mut vesting = user_vesting(storage, account)?;
+        vesting.push(Vesting {
+            amount: Uint128(claim),
+            claimed: Uint128::zero(),
+            start: time,
+            end: time + vesting_period,
+        });
+        UserVesting(vesting).save(storage, account.as_str().as_bytes())?;
+    }
+
+    Ok(())
+}
+
+pub fn user_vesting<S: Storage>(storage: &S, account: &HumanAddr) -> StdResult<Vec<Vesting>> {
+    Ok(UserVesting::may_load(storage, account.as_str().as_bytes())?
+        .map_or(vec![], |vesting| vesting.0))
+}
+
+///
+/// Pays out every unlocked vesting reward
+///
+pub fn try_claim_vested<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let sender = &env.message.sender;
+    let symbol = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .symbol;
+
+    let mut vesting = user_vesting(&deps.storage, sender)?;
+
+    let mut claim = Uint128::zero();
+    for item in vesting.iter_mut() {
+        let claimable = Uint128(item.claimable(env.block.time));
+        item.claimed += claimable;
+        claim += claimable;
+    }
+
+    if claim == Uint128::zero() {
+        return Err(ContractError::NothingToClaim.into());
+    }
+
+    // Fully claimed items are no longer needed
+    vesting.retain(|item| item.claimed != item.amount);
+    UserVesting(vesting).save(&mut deps.storage, sender.as_str().as_bytes())?;
+
+    store_claim_reward(
+        &mut deps.storage,
+        &deps.api.canonical_address(sender)?,
+        None,
+        claim,
+        symbol,
+        Some("Vested rewards".to_string()),
+        &env.block,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![send_msg(
+            sender.clone(),
+            claim,
+            None,
+            None,
+            None,
+            256,
+            stake_config.staked_token.code_hash,
+            stake_config.staked_token.address,
+        )?],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::ClaimVested { status: Success })?),
+    })
+}
+
+pub fn try_set_auto_compound<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    enabled: bool,
+) -> StdResult<HandleResponse> {
+    AutoCompound(enabled).save(&mut deps.storage, env.message.sender.as_str().as_bytes())?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::SetAutoCompound {
+            status: Success,
+        })?),
+    })
+}
+
+///
+/// Lowers the staked tokens and the unfunded unbondings pro rata to a loss in the treasury
+///
+pub fn try_report_loss<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+    memo: Option<String>,
+) -> StdResult<HandleResponse> {
+    let stake_config = StakeConfig::load(&deps.storage)?;
+
+    // The treasury holds the staked tokens so its allowed to report losses
+    if stake_config.treasury.as_ref() != Some(&env.message.sender) {
+        let config = Config::from_storage(&mut deps.storage);
+        check_if_admin(&config, &env.message.sender)?;
+    }
+
+    update_reward_drip(&mut deps.storage, env.block.time)?;
+
+    let mut total_tokens = TotalTokens::load(&deps.storage)?;
+    let mut total_unbonding = TotalUnbonding::load(&deps.storage)?;
+    let mut days = vec![];
+    let mut day = None;
+    while let Some(next) = next_unfunded_day(&deps.storage, day)? {
+        days.push(daily_unbonding(&deps.storage, next)?);
+        day = Some(next);
+    }
+
+    // Everything the treasury is still expected to pay back, tokens backing lock bonuses,
+    // staked tokens still held by the contract and released rewards never reached it
+    let released_rewards = HeldRewards::load(&deps.storage)?
+        .0
+        .u128()
+        .saturating_sub(RewardDrip::load(&deps.storage)?.remaining.u128());
+    let held = TotalLockBonus::load(&deps.storage)?.0.u128()
+        + available_liquidity(&deps.storage)?.u128()
+        + released_rewards;
+    let treasury_tokens = total_tokens.0.u128().saturating_sub(held);
+    let mut exposed = treasury_tokens;
+    for item in days.iter() {
+        exposed += (item.unbonding - item.funded)?.u128();
+    }
+
+    if amount.u128() >= exposed {
+        return Err(ContractError::LossTooLarge.into());
+    }
+
+    // Funded unbondings are already out of the treasury so only the unfunded part takes a loss
+    let mut unbonding_lost = 0;
+    for mut item in days {
+        let unfunded = (item.unbonding - item.funded)?.u128();
+        let lost =
+            (u256::from(amount.u128()) * u256::from(unfunded) / u256::from(exposed)).as_u128();
+        if lost == 0 {
+            continue;
+        }
+
+        let remaining = item.unbonding.u128() - lost;
+        let loss = u256::from(unbonding_loss(&deps.storage, item.release)?) * u256::from(remaining)
+            / u256::from(item.unbonding.u128());
+        // Records are keyed by the start of their epoch
+        let key = item.release.to_be_bytes();
+        UnbondingLoss(Uint128(loss.as_u128())).save(&mut deps.storage, &key)?;
+
+        // The loss is proportional to the unfunded part so the epoch stays unfunded
+        item.unbonding = Uint128(remaining);
+        DailyUnbondingRecord(item).save(&mut deps.storage, &key)?;
+        unbonding_lost += lost;
+    }
+
+    total_unbonding.0 = (total_unbonding.0 - Uint128(unbonding_lost))?;
+    total_unbonding.save(&mut deps.storage)?;
 
-            let mut daily_unbond_queue = DailyUnbondingQueue::load(&deps.storage)?;
+    // Whatever is left lowers the share price
+    let tokens_lost = amount.u128() - unbonding_lost;
+    if tokens_lost > treasury_tokens {
+        return Err(ContractError::InsufficientTokens.into());
+    }
+    total_tokens.0 = Uint128(total_tokens.0.u128() - tokens_lost);
+    total_tokens.save(&mut deps.storage)?;
+    LastRateChange(env.block.time).save(&mut deps.storage)?;
+
+    // Kept apart from the reporter's history so stakers can see what hit them
+    let count = ReportedLossCount::load(&deps.storage)?.0;
+    ReportedLoss {
+        amount,
+        staked: Uint128(tokens_lost),
+        unbonding: Uint128(unbonding_lost),
+        time: env.block.time,
+        memo: memo.clone(),
+    }
+    .save(&mut deps.storage, &count.to_be_bytes())?;
+    ReportedLossCount(count + 1).save(&mut deps.storage)?;
+
+    let symbol = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .symbol;
+    store_report_loss(
+        &mut deps.storage,
+        &deps.api.canonical_address(&env.message.sender)?,
+        amount,
+        symbol,
+        memo,
+        &env.block,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::ReportLoss { status: Success })?),
+    })
+}
+
+///
+/// Moves the collected rounding dust back into the share price or out to the treasury
+///
+pub fn try_sweep_rounding_dust<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    to_treasury: bool,
+) -> StdResult<HandleResponse> {
+    let config = Config::from_storage(&mut deps.storage);
 
-            while !daily_unbond_queue.0 .0.is_empty() {
-                remaining_amount = daily_unbond_queue.0 .0[0].fund(remaining_amount);
-                if daily_unbond_queue.0 .0[0].is_funded() {
-                    daily_unbond_queue.0 .0.pop();
-                }
-                if remaining_amount == Uint128::zero() {
-                    break;
-                }
-            }
+    check_if_admin(&config, &env.message.sender)?;
 
-            daily_unbond_queue.save(&mut deps.storage)?;
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let mut dust = RoundingDust::load(&deps.storage)?;
 
-            // Send back if overfunded
-            if remaining_amount > Uint128::zero() {
-                messages.push(send_msg(
-                    sender,
-                    remaining_amount,
-                    None,
-                    None,
-                    None,
-                    256,
-                    stake_config.staked_token.code_hash,
-                    stake_config.staked_token.address,
-                )?);
-            }
+    if dust.tokens == Uint128::zero() {
+        return Err(ContractError::NoRoundingDust.into());
+    }
 
-            store_fund_unbond(
-                &mut deps.storage,
-                &sender_canon,
-                (amount - remaining_amount)?,
-                symbol,
+    let mut messages = vec![];
+    if to_treasury {
+        if let Some(treasury) = stake_config.treasury {
+            messages.push(send_msg(
+                treasury,
+                dust.tokens,
                 None,
-                &env.block,
-            )?;
+                None,
+                None,
+                256,
+                stake_config.staked_token.code_hash,
+                stake_config.staked_token.address,
+            )?);
+        } else {
+            return Err(ContractError::NoTreasury.into());
         }
-    };
+    } else {
+        update_reward_drip(&mut deps.storage, env.block.time)?;
+        let mut total_tokens = TotalTokens::load(&deps.storage)?;
+        total_tokens.0 += dust.tokens;
+        total_tokens.save(&mut deps.storage)?;
+        LastRateChange(env.block.time).save(&mut deps.storage)?;
+    }
+
+    dust.tokens = Uint128::zero();
+    dust.save(&mut deps.storage)?;
 
     Ok(HandleResponse {
         messages,
         log: vec![],
-        data: Some(to_binary(&HandleAnswer::Receive { status: Success })?),
+        data: Some(to_binary(&HandleAnswer::SweepRoundingDust {
+            status: Success,
+        })?),
+    })
+}
+
+pub fn try_lock_stake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+    duration: u64,
+) -> StdResult<HandleResponse> {
+    let sender = &env.message.sender;
+    let sender_canon = &deps.api.canonical_address(sender)?;
+
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let symbol = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .symbol;
+
+    update_reward_drip(&mut deps.storage, env.block.time)?;
+    lock_tokens(
+        &mut deps.storage,
+        &stake_config,
+        sender,
+        sender_canon,
+        amount,
+        duration,
+        symbol,
+        &env.block,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::LockStake { status: Success })?),
     })
 }
 
@@ -539,9 +2103,29 @@ pub fn try_unbond<S: Storage, A: Api, Q: Querier>(
     let sender_canon = deps.api.canonical_address(&sender)?;
 
     let stake_config = StakeConfig::load(&deps.storage)?;
+    let symbol = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .symbol;
+    let mut messages = vec![];
 
     // Try to claim before unbonding
-    let claim = claim_rewards(&mut deps.storage, &stake_config, &sender, &sender_canon)?;
+    let claim = claim_rewards(
+        &mut deps.storage,
+        &stake_config,
+        &sender,
+        &sender_canon,
+        &env.block,
+    )?;
+    payout_implicit_claim(
+        &mut deps.storage,
+        &mut messages,
+        &stake_config,
+        &sender,
+        &sender_canon,
+        claim,
+        symbol.clone(),
+        &env.block,
+    )?;
 
     // Subtract tokens from user balance
     remove_balance(
@@ -553,63 +2137,334 @@ pub fn try_unbond<S: Storage, A: Api, Q: Querier>(
         env.block.time,
     )?;
 
+    // The fee stays with the treasury or the unsent staked tokens, it's just never paid back
+    let fee = Uint128(calculate_fee(
+        amount.u128(),
+        StakeFees::load(&deps.storage)?.unbond,
+    ));
+    let amount = (amount - fee)?;
+    if fee != Uint128::zero() {
+        store_fee(
+            &mut deps.storage,
+            &sender_canon,
+            fee,
+            symbol.clone(),
+            None,
+            &env.block,
+        )?;
+    }
+
     let mut total_unbonding = TotalUnbonding::load(&deps.storage)?;
     total_unbonding.0 += amount;
     total_unbonding.save(&mut deps.storage)?;
 
-    // Round to that day's public unbonding queue, initialize one if empty
-    let mut daily_unbond_queue = DailyUnbondingQueue::load(&deps.storage)?;
-    // Will add or merge a new unbonding date
-    daily_unbond_queue.0.push(&DailyUnbonding {
-        unbonding: amount,
-        funded: Default::default(),
-        release: round_date(env.block.time + stake_config.unbond_time),
-    });
+    let release = env.block.time + stake_config.unbond_time;
+    if release > LastUnbondingRelease::load(&deps.storage)?.0 {
+        LastUnbondingRelease(release).save(&mut deps.storage)?;
+    }
+
+    // Round to that epoch's public unbonding record, a funded epoch is owed again
+    let epoch = UnbondingEpochs::load(&deps.storage)?.round(release);
+    let mut daily = daily_unbonding(&deps.storage, epoch)?;
+    let was_funded = daily.is_funded();
+    daily.unbonding += amount;
+    DailyUnbondingRecord(daily).save(&mut deps.storage, &epoch.to_be_bytes())?;
+
+    if was_funded {
+        add_unfunded_day(&mut deps.storage, epoch)?;
+    }
 
-    daily_unbond_queue.save(&mut deps.storage)?;
+    notify_treasury(
+        &deps.storage,
+        &mut messages,
+        &stake_config,
+        TreasuryHandleMsg::Unbonded {
+            amount,
+            release: epoch,
+        },
+    )?;
 
     // Check if user has an existing queue, if not, init one
     let mut unbond_queue = UnbondingQueue::may_load(&deps.storage, sender.as_str().as_bytes())?
         .unwrap_or(UnbondingQueue(VecQueue::new(vec![])));
 
-    // Add unbonding to user queue
-    unbond_queue.0.push(&Unbonding {
-        amount,
-        release: env.block.time + stake_config.unbond_time,
-    });
+    let mut ids = unbonding_ids(&deps.storage, &sender, unbond_queue.0 .0.len())?;
+
+    // Every unbonding gets its own entry, after the ones released at the same time
+    let entries = &mut unbond_queue.0 .0;
+    let index = entries
+        .iter()
+        .position(|item| item.release > release)
+        .unwrap_or(entries.len());
+    entries.insert(
+        index,
+        Unbonding {
+            amount: normalize_unbonding(&deps.storage, amount, release)?,
+            release,
+        },
+    );
+    let id = next_unbonding_id(&mut deps.storage)?;
+    ids.insert(index, id);
 
     unbond_queue.save(&mut deps.storage, sender.as_str().as_bytes())?;
+    UnbondingIds(ids).save(&mut deps.storage, sender.as_str().as_bytes())?;
 
     // Store the tx
+    store_unbond(
+        &mut deps.storage,
+        &deps.api.canonical_address(&sender)?,
+        amount,
+        symbol,
+        None,
+        &env.block,
+    )?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Unbond {
+            status: Success,
+            id,
+        })?),
+    })
+}
+
+///
+/// Takes back the newest pending unbondings and stakes them again
+///
+pub fn try_cancel_unbond<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let sender = env.message.sender;
+    let sender_canon = deps.api.canonical_address(&sender)?;
+
+    let stake_config = StakeConfig::load(&deps.storage)?;
     let symbol = ReadonlyConfig::from_storage(&deps.storage)
         .constants()?
         .symbol;
+
+    // Cancelled unbondings are bonded again so they have to fit under the caps
+    let room = bond_room(&deps.storage, &sender_canon)?;
+    if amount.u128() > room {
+        return Err(ContractError::BondOverLimit {
+            room: Uint128(room),
+        }
+        .into());
+    }
+
+    let mut unbond_queue = UnbondingQueue::may_load(&deps.storage, sender.as_str().as_bytes())?
+        .ok_or_else(|| StdError::from(ContractError::InsufficientUnbonding))?;
+    let mut ids = unbonding_ids(&deps.storage, &sender, unbond_queue.0 .0.len())?;
+    let epochs = UnbondingEpochs::load(&deps.storage)?;
+
+    let mut remaining = amount;
+    // Tokens the treasury already sent back for the cancelled unbondings
+    let mut funded = Uint128::zero();
+
+    // The queue is sorted so the newest unbondings are at the end
+    while remaining != Uint128::zero() {
+        let item = match unbond_queue.0 .0.last() {
+            Some(item) if item.release > env.block.time => Unbonding {
+                amount: item.amount,
+                release: item.release,
+            },
+            _ => return Err(ContractError::InsufficientUnbonding.into()),
+        };
+
+        let value = unbonding_value(&deps.storage, &item)?;
+        let cancelled = value.min(remaining);
+        remaining = (remaining - cancelled)?;
+
+        if cancelled == value {
+            unbond_queue.0 .0.pop();
+            ids.pop();
+        } else {
+            let index = unbond_queue.0 .0.len() - 1;
+            unbond_queue.0 .0[index].amount =
+                normalize_unbonding(&deps.storage, (value - cancelled)?, item.release)?;
+        }
+
+        let epoch = epochs.round(item.release);
+        let mut daily = daily_unbonding(&deps.storage, epoch)?;
+        // Losses are rounded separately for the epoch and the user
+        daily.unbonding = Uint128(daily.unbonding.u128().saturating_sub(cancelled.u128()));
+
+        // Funding over what the epoch still owes is not needed anymore
+        if daily.funded > daily.unbonding {
+            funded += (daily.funded - daily.unbonding)?;
+            daily.funded = daily.unbonding;
+        }
+        if daily.is_funded() {
+            remove_unfunded_day(&mut deps.storage, epoch)?;
+        }
+        DailyUnbondingRecord(daily).save(&mut deps.storage, &epoch.to_be_bytes())?;
+    }
+
+    unbond_queue.save(&mut deps.storage, sender.as_str().as_bytes())?;
+    UnbondingIds(ids).save(&mut deps.storage, sender.as_str().as_bytes())?;
+
+    let mut total_unbonding = TotalUnbonding::load(&deps.storage)?;
+    total_unbonding.0 = (total_unbonding.0 - amount)?;
+    total_unbonding.save(&mut deps.storage)?;
+
+    add_balance(
+        &mut deps.storage,
+        &stake_config,
+        &sender,
+        &sender_canon,
+        amount.u128(),
+        env.block.time,
+    )?;
+
+    store_stake(
+        &mut deps.storage,
+        &sender_canon,
+        amount,
+        symbol,
+        None,
+        &env.block,
+    )?;
+
+    // The treasury keeps backing the unfunded part, the funded part is sent back to it
     let mut messages = vec![];
-    if claim != 0 {
-        messages.push(send_msg(
-            sender.clone(),
-            Uint128(claim),
-            None,
-            None,
-            None,
-            256,
-            stake_config.staked_token.code_hash,
-            stake_config.staked_token.address,
-        )?);
+    let mut kept = Uint128::zero();
+    if funded != Uint128::zero() {
+        let sent = forward_staked_tokens(&mut deps.storage, &mut messages, &stake_config, funded)?;
+        kept = (funded - sent)?;
+    }
+    // Everything but what the buffer kept is bonded at the treasury again
+    notify_bonded(
+        &deps.storage,
+        &mut messages,
+        &stake_config,
+        (amount - kept)?,
+    )?;
 
-        store_claim_reward(
-            &mut deps.storage,
-            &sender_canon,
-            Uint128(claim),
-            symbol.clone(),
-            None,
-            &env.block,
-        )?;
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::CancelUnbond { status: Success })?),
+    })
+}
+
+///
+/// Staked tokens that can be paid out right away
+///
+pub fn available_liquidity<S: Storage>(storage: &S) -> StdResult<Uint128> {
+    let buffer = LiquidityBuffer::load(storage)?.0.u128();
+    let unsent = UnsentStakedTokens::load(storage)?.0.u128();
+
+    if let Some(liquidity) = buffer.checked_add(unsent) {
+        Ok(Uint128(liquidity))
+    } else {
+        Err(ContractError::Overflow("Liquidity overflow").into())
+    }
+}
+
+///
+/// Unbonds and pays out from the held staked tokens, the penalty is left for the remaining stakers
+///
+pub fn try_instant_unbond<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let sender = env.message.sender;
+    let sender_canon = deps.api.canonical_address(&sender)?;
+
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let symbol = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .symbol;
+    let mut messages = vec![];
+
+    // Try to claim before unbonding
+    let claim = claim_rewards(
+        &mut deps.storage,
+        &stake_config,
+        &sender,
+        &sender_canon,
+        &env.block,
+    )?;
+    payout_implicit_claim(
+        &mut deps.storage,
+        &mut messages,
+        &stake_config,
+        &sender,
+        &sender_canon,
+        claim,
+        symbol.clone(),
+        &env.block,
+    )?;
+
+    // Subtract tokens from user balance
+    remove_balance(
+        &mut deps.storage,
+        &stake_config,
+        &sender,
+        &sender_canon,
+        amount.u128(),
+        env.block.time,
+    )?;
+
+    let penalty = Uint128(calculate_fee(
+        amount.u128(),
+        InstantUnbondConfig::load(&deps.storage)?.penalty,
+    ));
+    let payout = (amount - penalty)?;
+
+    if payout > available_liquidity(&deps.storage)? {
+        return Err(ContractError::InsufficientLiquidity.into());
+    }
+
+    // Use up the buffer before the tokens waiting for a treasury
+    let mut buffer = LiquidityBuffer::load(&deps.storage)?;
+    let from_buffer = buffer.0.min(payout);
+    buffer.0 = (buffer.0 - from_buffer)?;
+    buffer.save(&mut deps.storage)?;
+
+    let from_unsent = (payout - from_buffer)?;
+    if from_unsent != Uint128::zero() {
+        let mut unsent = UnsentStakedTokens::load(&deps.storage)?;
+        unsent.0 = (unsent.0 - from_unsent)?;
+        unsent.save(&mut deps.storage)?;
+    }
+
+    // The penalty stays staked and raises the share price
+    if penalty != Uint128::zero() {
+        let mut total_tokens = TotalTokens::load(&deps.storage)?;
+        total_tokens.0 += penalty;
+        total_tokens.save(&mut deps.storage)?;
+        LastRateChange(env.block.time).save(&mut deps.storage)?;
     }
+
+    messages.push(send_msg(
+        sender.clone(),
+        payout,
+        None,
+        None,
+        None,
+        256,
+        stake_config.staked_token.code_hash,
+        stake_config.staked_token.address,
+    )?);
+
+    // Store the tx
     store_unbond(
         &mut deps.storage,
-        &deps.api.canonical_address(&sender)?,
+        &sender_canon,
         amount,
+        symbol.clone(),
+        None,
+        &env.block,
+    )?;
+    store_claim_unbond(
+        &mut deps.storage,
+        &sender_canon,
+        None,
+        payout,
         symbol,
         None,
         &env.block,
@@ -618,13 +2473,19 @@ pub fn try_unbond<S: Storage, A: Api, Q: Querier>(
     Ok(HandleResponse {
         messages,
         log: vec![],
-        data: Some(to_binary(&HandleAnswer::Unbond { status: Success })?),
+        data: Some(to_binary(&HandleAnswer::InstantUnbond { status: Success })?),
     })
 }
 
+///
+/// Claims released and funded unbondings, optionally only the given IDs and up to an amount
+///
 pub fn try_claim_unbond<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    ids: Option<Vec<u64>>,
+    amount: Option<Uint128>,
+    payout: Payout,
 ) -> StdResult<HandleResponse> {
     let sender = &env.message.sender;
     let sender_canon = &deps.api.canonical_address(sender)?;
@@ -633,39 +2494,67 @@ pub fn try_claim_unbond<S: Storage, A: Api, Q: Querier>(
 
     let mut total_unbonding = TotalUnbonding::load(&deps.storage)?;
 
-    // Instead of iterating over it we just look at its smallest value (first in queue)
-    let daily_unbond_queue = DailyUnbondingQueue::load(&deps.storage)?.0;
-
-    // Check if user has an existing queue, if not, init one
+    // Users that never unbonded have nothing to claim
     let mut unbond_queue = UnbondingQueue::may_load(&deps.storage, sender.as_str().as_bytes())?
-        .expect("No unbonding queue found");
+        .ok_or_else(|| StdError::from(ContractError::NothingToClaim))?;
+
+    let queued_ids = unbonding_ids(&deps.storage, sender, unbond_queue.0 .0.len())?;
+    let epochs = UnbondingEpochs::load(&deps.storage)?;
 
     let mut total = Uint128::zero();
-    // Iterate over the sorted queue
-    while !unbond_queue.0 .0.is_empty() {
-        // Since the queue is sorted, the moment we find a date above the current then we assume
-        // that no other item in the queue is eligible
-        if unbond_queue.0 .0[0].release <= env.block.time {
-            // Daily unbond queue is also sorted, therefore as long as its next item is greater
-            // than the unbond then we assume its funded
-            if daily_unbond_queue.0.is_empty()
-                || round_date(unbond_queue.0 .0[0].release) < daily_unbond_queue.0[0].release
-            {
-                total += unbond_queue.0 .0[0].amount;
-                unbond_queue.0.pop();
-            } else {
-                break;
-            }
-        } else {
-            break;
+    let mut claimable_ids = vec![];
+    let mut claimed_ids = vec![];
+    let mut remaining = vec![];
+    let mut remaining_ids = vec![];
+    // Iterate over the sorted queue, oldest first
+    for (item, id) in unbond_queue.0 .0.drain(..).zip(queued_ids) {
+        // The epoch the unbonding is released in must be fully funded
+        let claimable = item.release <= env.block.time
+            && daily_unbonding(&deps.storage, epochs.round(item.release))?.is_funded();
+        let selected = ids.as_ref().map_or(true, |ids| ids.contains(&id));
+        if !claimable || !selected {
+            remaining.push(item);
+            remaining_ids.push(id);
+            continue;
+        }
+        claimable_ids.push(id);
+
+        let left = amount.map(|amount| amount.u128() - total.u128());
+        if left == Some(0) {
+            remaining.push(item);
+            remaining_ids.push(id);
+            continue;
+        }
+
+        let value = unbonding_value(&deps.storage, &item)?.u128();
+        let claim = left.map_or(value, |left| left.min(value));
+        if claim < value {
+            // Keep the rest under the same ID
+            remaining.push(Unbonding {
+                amount: normalize_unbonding(&deps.storage, Uint128(value - claim), item.release)?,
+                release: item.release,
+            });
+            remaining_ids.push(id);
+        }
+
+        total += Uint128(claim);
+        claimed_ids.push(id);
+    }
+    unbond_queue.0 .0 = remaining;
+
+    // Asking for an ID that cannot be claimed is an error instead of a silent skip
+    if let Some(ids) = &ids {
+        if let Some(id) = ids.iter().find(|id| !claimable_ids.contains(*id)) {
+            return Err(ContractError::UnbondingNotClaimable { id: *id }.into());
         }
     }
 
     if total == Uint128::zero() {
-        return Err(StdError::generic_err("Nothing to claim"));
+        return Err(ContractError::NothingToClaim.into());
     }
 
     unbond_queue.save(&mut deps.storage, sender.as_str().as_bytes())?;
+    UnbondingIds(remaining_ids).save(&mut deps.storage, sender.as_str().as_bytes())?;
     total_unbonding.0 = (total_unbonding.0 - total)?;
     total_unbonding.save(&mut deps.storage)?;
 
@@ -675,155 +2564,408 @@ pub fn try_claim_unbond<S: Storage, A: Api, Q: Querier>(
     store_claim_unbond(
         &mut deps.storage,
         sender_canon,
+        payout.history_recipient(&deps.api, sender)?,
         total,
         symbol,
         None,
         &env.block,
     )?;
 
-    let messages = vec![send_msg(
-        sender.clone(),
-        total,
-        None,
-        None,
-        None,
-        256,
-        stake_config.staked_token.code_hash,
-        stake_config.staked_token.address,
-    )?];
+    let messages = vec![payout.cosmos_msg(&stake_config.staked_token, total)?];
 
     Ok(HandleResponse {
         messages,
         log: vec![],
-        data: Some(to_binary(&HandleAnswer::ClaimUnbond { status: Success })?),
+        data: Some(to_binary(&HandleAnswer::ClaimUnbond {
+            status: Success,
+            ids: claimed_ids,
+        })?),
     })
 }
 
 pub fn try_claim_rewards<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    payout: Payout,
+) -> StdResult<HandleResponse> {
+    let owner = env.message.sender.clone();
+
+    Ok(HandleResponse {
+        messages: claim_account_rewards(deps, &env, &owner, 0, None, &payout)?,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::ClaimRewards { status: Success })?),
+    })
+}
+
+pub fn try_claim_rewards_for<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    owner: HumanAddr,
+    tip: Option<Uint128>,
 ) -> StdResult<HandleResponse> {
+    let approval = load_operator(deps, &env, &owner)?;
+
+    Ok(HandleResponse {
+        messages: claim_account_rewards(
+            deps,
+            &env,
+            &owner,
+            approval.max_tip,
+            tip,
+            &Payout::to(&owner),
+        )?,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::ClaimRewardsFor {
+            status: Success,
+        })?),
+    })
+}
+
+///
+/// Claims the owner's rewards, paying the requested tip to the sender
+///
+fn claim_account_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    owner: &HumanAddr,
+    max_tip: u64,
+    tip: Option<Uint128>,
+    payout: &Payout,
+) -> StdResult<Vec<CosmosMsg>> {
     let stake_config = StakeConfig::load(&deps.storage)?;
 
-    let sender = &env.message.sender;
-    let sender_canon = &deps.api.canonical_address(sender)?;
+    let owner_canon = &deps.api.canonical_address(owner)?;
+
+    let claim = claim_rewards(
+        &mut deps.storage,
+        &stake_config,
+        owner,
+        owner_canon,
+        &env.block,
+    )?;
 
-    let claim = claim_rewards(&mut deps.storage, &stake_config, sender, sender_canon)?;
+    let shares = UserShares::load(&deps.storage, owner.as_str().as_bytes())?.0;
+    let token_claims = claim_reward_tokens(&mut deps.storage, owner, shares.u128())?;
 
-    if claim == 0 {
-        return Err(StdError::generic_err("Nothing to claim"));
+    if claim == 0 && token_claims.is_empty() {
+        return Err(ContractError::NothingToClaim.into());
     }
 
-    let messages = vec![send_msg(
-        sender.clone(),
-        Uint128(claim),
-        None,
-        None,
-        None,
-        256,
-        stake_config.staked_token.code_hash,
-        stake_config.staked_token.address,
-    )?];
+    // Vesting rewards are added to the owner's ledger so they can't go anywhere else
+    if claim != 0 && RewardVestingPeriod::load(&deps.storage)?.0 != 0 && !payout.is_owner(owner) {
+        return Err(ContractError::VestingRecipient.into());
+    }
+
+    let mut messages = vec![];
+    let recipient = payout.history_recipient(&deps.api, owner)?;
 
     let symbol = ReadonlyConfig::from_storage(&deps.storage)
         .constants()?
         .symbol;
-    store_claim_reward(
+    let tip = pay_operator_tip(
         &mut deps.storage,
-        sender_canon,
-        Uint128(claim),
-        symbol,
-        None,
-        &env.block,
+        &mut messages,
+        &stake_config,
+        env,
+        owner_canon,
+        max_tip,
+        tip,
+        claim,
     )?;
 
+    if claim != 0 {
+        if claim != tip {
+            pay_rewards(
+                &mut deps.storage,
+                &mut messages,
+                &stake_config,
+                owner,
+                payout,
+                claim - tip,
+                env.block.time,
+            )?;
+        }
+
+        store_claim_reward(
+            &mut deps.storage,
+            owner_canon,
+            recipient.clone(),
+            Uint128(claim),
+            symbol,
+            None,
+            &env.block,
+        )?;
+    }
+
+    for (token, amount) in token_claims {
+        messages.push(payout.cosmos_msg(&token, amount)?);
+
+        store_claim_reward(
+            &mut deps.storage,
+            owner_canon,
+            recipient.clone(),
+            amount,
+            token.address.to_string(),
+            None,
+            &env.block,
+        )?;
+    }
+
+    Ok(messages)
+}
+
+pub fn try_stake_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let owner = env.message.sender.clone();
+
     Ok(HandleResponse {
-        messages,
+        messages: stake_account_rewards(deps, &env, &owner, 0, None)?,
         log: vec![],
-        data: Some(to_binary(&HandleAnswer::ClaimRewards { status: Success })?),
+        data: Some(to_binary(&HandleAnswer::StakeRewards { status: Success })?),
     })
 }
 
-pub fn try_stake_rewards<S: Storage, A: Api, Q: Querier>(
+pub fn try_stake_rewards_for<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    owner: HumanAddr,
+    tip: Option<Uint128>,
 ) -> StdResult<HandleResponse> {
+    let approval = load_operator(deps, &env, &owner)?;
+
+    Ok(HandleResponse {
+        messages: stake_account_rewards(deps, &env, &owner, approval.max_tip, tip)?,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::StakeRewardsFor {
+            status: Success,
+        })?),
+    })
+}
+
+///
+/// Restakes the owner's rewards, paying the requested tip to the sender
+///
+fn stake_account_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    owner: &HumanAddr,
+    max_tip: u64,
+    tip: Option<Uint128>,
+) -> StdResult<Vec<CosmosMsg>> {
     // Clam rewards
     let symbol = ReadonlyConfig::from_storage(&deps.storage)
         .constants()?
         .symbol;
     let stake_config = StakeConfig::load(&deps.storage)?;
 
-    let sender = &env.message.sender;
-    let sender_canon = &deps.api.canonical_address(sender)?;
+    let owner_canon = &deps.api.canonical_address(owner)?;
 
     let claim = Uint128(claim_rewards(
         &mut deps.storage,
         &stake_config,
-        sender,
-        sender_canon,
+        owner,
+        owner_canon,
+        &env.block,
     )?);
 
     store_claim_reward(
         &mut deps.storage,
-        sender_canon,
+        owner_canon,
+        None,
         claim,
         symbol.clone(),
         None,
         &env.block,
     )?;
 
-    // Stake rewards
-    // Update user stake
-    add_balance(
+    let mut messages = vec![];
+
+    let tip = Uint128(pay_operator_tip(
         &mut deps.storage,
+        &mut messages,
         &stake_config,
-        sender,
-        sender_canon,
+        env,
+        owner_canon,
+        max_tip,
+        tip,
         claim.u128(),
+    )?);
+    let claim = (claim - tip)?;
+
+    let staked = Uint128(bond_allowance(&deps.storage, owner_canon, claim.u128())?);
+
+    if staked != Uint128::zero() {
+        // Stake rewards
+        // Update user stake
+        add_balance(
+            &mut deps.storage,
+            &stake_config,
+            owner,
+            owner_canon,
+            staked.u128(),
+            env.block.time,
+        )?;
+
+        // Store data
+        store_stake(
+            &mut deps.storage,
+            owner_canon,
+            staked,
+            symbol,
+            None,
+            &env.block,
+        )?;
+
+        // Send tokens
+        let sent = forward_staked_tokens(&mut deps.storage, &mut messages, &stake_config, staked)?;
+        notify_bonded(&deps.storage, &mut messages, &stake_config, sent)?;
+    }
+
+    // Rewards over the limits are paid out instead
+    let unstaked = (claim - staked)?;
+    if unstaked != Uint128::zero() {
+        pay_rewards(
+            &mut deps.storage,
+            &mut messages,
+            &stake_config,
+            owner,
+            &Payout::to(owner),
+            unstaked.u128(),
+            env.block.time,
+        )?;
+    }
+
+    Ok(messages)
+}
+
+///
+/// Loads the sender's operator approval for the owner
+///
+fn load_operator<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    owner: &HumanAddr,
+) -> StdResult<Operator> {
+    let approval = read_operator(
+        &deps.storage,
+        &deps.api.canonical_address(owner)?,
+        &deps.api.canonical_address(&env.message.sender)?,
     )?;
 
-    // Store data
-    // Store data
-    store_stake(
-        &mut deps.storage,
-        sender_canon,
-        claim,
+    match approval {
+        Some(approval) if !approval.is_expired_at(&env.block) => Ok(approval),
+        _ => Err(ContractError::NotOperator {
+            operator: env.message.sender.clone(),
+            owner: owner.clone(),
+        }
+        .into()),
+    }
+}
+
+///
+/// Sends the requested tip out of the claimed rewards to the sender
+///
+#[allow(clippy::too_many_arguments)]
+fn pay_operator_tip<S: Storage>(
+    storage: &mut S,
+    messages: &mut Vec<CosmosMsg>,
+    stake_config: &StakeConfig,
+    env: &Env,
+    owner_canon: &CanonicalAddr,
+    max_tip: u64,
+    tip: Option<Uint128>,
+    claim: u128,
+) -> StdResult<u128> {
+    let tip = tip.unwrap_or_default().u128();
+
+    if tip == 0 {
+        return Ok(0);
+    }
+
+    if tip > calculate_fee(claim, max_tip) {
+        return Err(ContractError::TipTooHigh { max_tip }.into());
+    }
+
+    // Tips are rewards too so they vest like any other
+    pay_rewards(
+        storage,
+        messages,
+        stake_config,
+        &env.message.sender,
+        &Payout::to(&env.message.sender),
+        tip,
+        env.block.time,
+    )?;
+
+    let symbol = ReadonlyConfig::from_storage(storage).constants()?.symbol;
+    store_fee(
+        storage,
+        owner_canon,
+        Uint128(tip),
         symbol,
-        None,
+        Some("Operator tip".to_string()),
         &env.block,
     )?;
 
-    let mut messages = vec![];
+    Ok(tip)
+}
 
-    // Send tokens
-    if let Some(treasury) = stake_config.treasury {
-        messages.push(send_msg(
-            treasury,
-            claim,
-            None,
-            None,
-            None,
-            256,
-            stake_config.staked_token.code_hash,
-            stake_config.staked_token.address,
-        )?);
-    } else {
-        let mut stored_tokens = UnsentStakedTokens::load(&deps.storage)?;
-        stored_tokens.0 += claim;
-        stored_tokens.save(&mut deps.storage)?;
+pub fn try_set_operator<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    operator: HumanAddr,
+    max_tip: u64,
+    expiration: Option<u64>,
+) -> StdResult<HandleResponse> {
+    if max_tip > FEE_PRECISION as u64 {
+        return Err(ContractError::InvalidConfig("Tip cannot be over 10000 basis points").into());
     }
 
+    write_operator(
+        &mut deps.storage,
+        &deps.api.canonical_address(&env.message.sender)?,
+        &deps.api.canonical_address(&operator)?,
+        Operator {
+            max_tip,
+            expiration,
+        },
+    )?;
+
     Ok(HandleResponse {
-        messages,
+        messages: vec![],
         log: vec![],
-        data: Some(to_binary(&HandleAnswer::StakeRewards { status: Success })?),
+        data: Some(to_binary(&HandleAnswer::SetOperator { status: Success })?),
+    })
+}
+
+pub fn try_revoke_operator<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    operator: HumanAddr,
+) -> StdResult<HandleResponse> {
+    remove_operator(
+        &mut deps.storage,
+        &deps.api.canonical_address(&env.message.sender)?,
+        &deps.api.canonical_address(&operator)?,
+    );
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RevokeOperator {
+            status: Success,
+        })?),
     })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::stake::{calculate_rewards, round_date, shares_per_token, tokens_per_share};
+    use crate::stake::{
+        calculate_rewards, minimum_stake, round_date, shares_per_token, tokens_per_share,
+    };
+    use crate::state_staking::{UnbondingEpoch, UnbondingEpochs};
     use shade_protocol::shd_staking::stake::StakeConfig;
     use shade_protocol::utils::asset::Contract;
 
@@ -901,6 +3043,10 @@ mod tests {
 
         // check that shares decrease when tokens increase
         assert!(shares_per_token(&config, &token_1, &token_1, &(share_1 * 2)).unwrap() > share_1);
+
+        // Shares without tokens can't be priced
+        assert_eq!(shares_per_token(&config, &0, &0, &share_1).unwrap(), 0);
+        assert!(shares_per_token(&config, &token_1, &0, &share_1).is_err());
     }
 
     #[test]
@@ -908,6 +3054,23 @@ mod tests {
         assert_eq!(round_date(1645740448), 1645660800)
     }
 
+    #[test]
+    fn unbonding_epochs_round_test() {
+        let epochs = UnbondingEpochs(vec![
+            UnbondingEpoch {
+                start: 0,
+                length: 86400,
+            },
+            UnbondingEpoch {
+                start: 1645747200,
+                length: 3600,
+            },
+        ]);
+        assert_eq!(epochs.round(1645740448), 1645660800);
+        assert_eq!(epochs.round(1645747200), 1645747200);
+        assert_eq!(epochs.round(1645752000), 1645750800);
+    }
+
     #[test]
     fn calculate_rewards_test() {
         let token_decimals = 8;
@@ -973,6 +3136,27 @@ mod tests {
         assert_eq!(reward_token, 0);
     }
 
+    #[test]
+    fn empty_pool_shares_test() {
+        let token_decimals = 8;
+        let shares_decimals = 18;
+        let config = init_config(token_decimals, shares_decimals);
+
+        let token_1 = 100 * 10u128.pow(token_decimals.into());
+        let share_1 = 100 * 10u128.pow(shares_decimals.into());
+
+        // Tokens left in a pool without shares must not price new shares at zero
+        let leftover = 50 * 10u128.pow(token_decimals.into());
+        assert_eq!(
+            shares_per_token(&config, &token_1, &leftover, &0).unwrap(),
+            share_1
+        );
+        assert_eq!(
+            tokens_per_share(&config, &share_1, &leftover, &0).unwrap(),
+            token_1
+        );
+    }
+
     use rand::Rng;
     use secret_cosmwasm_math_compat::Uint128;
 
@@ -1041,4 +3225,16 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn minimum_stake_test() {
+        // A pool must hold the same fraction of a token whatever its decimals
+        assert_eq!(minimum_stake(18), 10u128.pow(13));
+        assert_eq!(minimum_stake(8), 1_000);
+        assert_eq!(minimum_stake(6), 10);
+
+        // Tokens with few decimals still need at least one unit
+        assert_eq!(minimum_stake(2), 1);
+        assert_eq!(minimum_stake(0), 1);
+    }
 }
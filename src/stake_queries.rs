@@ -1,19 +1,36 @@
-use crate::msg::QueryAnswer;
-use crate::stake::{calculate_rewards, shares_per_token};
-use crate::state::ReadonlyBalances;
+use crate::errors::ContractError;
+use crate::msg::{AuditTotals, QueryAnswer, ScheduledUnbonding, UnbondingEntry};
+use crate::reward_tokens::pending_reward_tokens;
+use crate::stake::{
+    available_liquidity, calculate_rewards, daily_unbonding, is_auto_compounding, locked_bonus,
+    next_unfunded_day, shares_per_token, tokens_per_share, unbonding_ids, unbonding_value,
+    unfunded_day_after, user_locks, user_vesting,
+};
+use crate::state::{read_operator, ReadonlyBalances, ReadonlyConfig};
 use crate::state_staking::{
-    DailyUnbondingQueue, TotalShares, TotalTokens, TotalUnbonding, UnbondingQueue, UserCooldown,
-    UserShares,
+    BondLimits, Holder, HolderCount, InstantUnbondConfig, LastRateChange, Lock, LockTiers,
+    RateSnapshot, RateSnapshotCount, ReportedLoss, ReportedLossCount, RewardDrip, RewardDripPeriod,
+    RewardVestingPeriod, RoundingDust, StakeFees, TotalShares, TotalTokens, TotalUnbonding,
+    TreasuryCallback, UnbondingEpochs, UnbondingQueue, UserCooldown, UserShares,
 };
 use cosmwasm_std::{
-    to_binary, Api, Binary, Extern, HumanAddr, Querier, StdResult, Storage, Uint128,
+    to_binary, Api, Binary, Extern, HumanAddr, Querier, StdError, StdResult, Storage, Uint128,
 };
+use ethnum::u256;
 use shade_protocol::shd_staking::stake::{StakeConfig, VecQueue};
 use shade_protocol::utils::storage::{BucketStorage, SingletonStorage};
 
 pub fn stake_config<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
     to_binary(&QueryAnswer::StakedConfig {
         config: StakeConfig::load(&deps.storage)?,
+        reward_drip_period: RewardDripPeriod::load(&deps.storage)?.0,
+        lock_tiers: LockTiers::load(&deps.storage)?.0,
+        bond_limits: BondLimits::load(&deps.storage)?,
+        fees: StakeFees::load(&deps.storage)?,
+        instant_unbond: InstantUnbondConfig::load(&deps.storage)?,
+        reward_vesting_period: RewardVestingPeriod::load(&deps.storage)?.0,
+        unbonding_epochs: UnbondingEpochs::load(&deps.storage)?.0,
+        treasury_callback: TreasuryCallback::load(&deps.storage)?,
     })
 }
 
@@ -35,6 +52,196 @@ pub fn stake_rate<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> Std
     })
 }
 
+///
+/// Total tokens including the rewards dripped by the given time, without a time
+/// only what was released by the last state change is counted
+///
+fn total_tokens_at<S: Storage>(storage: &S, time: Option<u64>) -> StdResult<u128> {
+    let released = match time {
+        Some(time) => RewardDrip::load(storage)?.released(time),
+        None => 0,
+    };
+
+    TotalTokens::load(storage)?
+        .0
+        .u128()
+        .checked_add(released)
+        .ok_or_else(|| StdError::from(ContractError::Overflow("Total staked tokens overflow")))
+}
+
+const RATE_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+///
+/// Share price between whole staked tokens and whole shares as 18 decimal fixed point values
+///
+pub fn exchange_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    time: Option<u64>,
+) -> StdResult<Binary> {
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let total_shares = u256::from(TotalShares::load(&deps.storage)?.0.u128());
+    let precision = u256::from(RATE_PRECISION);
+
+    let stored_tokens = TotalTokens::load(&deps.storage)?.0.u128();
+    let tokens = total_tokens_at(&deps.storage, time)?;
+
+    // The drip moves the rate on its own
+    let last_update = match time {
+        Some(time) if tokens != stored_tokens => time,
+        _ => LastRateChange::load(&deps.storage)?.0,
+    };
+
+    // Shares have more decimals than the staked token
+    let total_tokens =
+        u256::from(tokens) * u256::from(10u16).pow(stake_config.decimal_difference.into());
+
+    // An empty pool stakes at the initial one to one rate
+    let (rate, shares_per_token) = if total_shares == u256::ZERO {
+        (precision, precision)
+    } else if total_tokens == u256::ZERO {
+        // Shares left without tokens are worth nothing and can't be staked into
+        (u256::ZERO, u256::ZERO)
+    } else {
+        (
+            total_tokens * precision / total_shares,
+            total_shares * precision / total_tokens,
+        )
+    };
+
+    if rate > u256::from(u128::MAX) || shares_per_token > u256::from(u128::MAX) {
+        return Err(ContractError::Overflow("Exchange rate overflow").into());
+    }
+
+    to_binary(&QueryAnswer::ExchangeRate {
+        rate: Uint128(rate.as_u128()),
+        denom: stake_config.staked_token.address.to_string(),
+        shares_per_token: Uint128(shares_per_token.as_u128()),
+        last_update,
+    })
+}
+
+pub fn reward_drip<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    time: Option<u64>,
+) -> StdResult<Binary> {
+    let drip = RewardDrip::load(&deps.storage)?;
+    let time = time.unwrap_or(drip.last_update);
+
+    to_binary(&QueryAnswer::RewardDrip {
+        remaining: Uint128(drip.remaining.u128() - drip.released(time)),
+        rate: drip.rate(time),
+        end: drip.end,
+    })
+}
+
+pub fn stake_rate_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    let total = RateSnapshotCount::load(&deps.storage)?.0;
+
+    // Newest snapshots first
+    let end = total.saturating_sub(page as u64 * page_size as u64);
+    let start = end.saturating_sub(page_size as u64);
+
+    let mut snapshots = vec![];
+    for i in (start..end).rev() {
+        snapshots.push(RateSnapshot::load(&deps.storage, &i.to_be_bytes())?);
+    }
+
+    to_binary(&QueryAnswer::StakeRateHistory { snapshots, total })
+}
+
+pub fn loss_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    let total = ReportedLossCount::load(&deps.storage)?.0;
+
+    // Newest losses first
+    let end = total.saturating_sub(page as u64 * page_size as u64);
+    let start = end.saturating_sub(page_size as u64);
+
+    let mut losses = vec![];
+    for i in (start..end).rev() {
+        losses.push(ReportedLoss::load(&deps.storage, &i.to_be_bytes())?);
+    }
+
+    to_binary(&QueryAnswer::LossHistory { losses, total })
+}
+
+const YEAR: u64 = 31_536_000; // 60 * 60 * 24 * 365
+const APR_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+///
+/// Annualizes the share price growth since the last snapshot taken before the window
+///
+pub fn estimated_apr<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    time: u64,
+    window: u64,
+) -> StdResult<Binary> {
+    let since = time.saturating_sub(window);
+
+    // Snapshots are sorted by day, find the last one on or before the start of the window
+    let mut low = 0;
+    let mut high = RateSnapshotCount::load(&deps.storage)?.0;
+    while low < high {
+        let mid = (low + high) / 2;
+        if RateSnapshot::load(&deps.storage, &mid.to_be_bytes())?.day <= since {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        return Err(ContractError::NoRateHistory.into());
+    }
+    let snapshot = RateSnapshot::load(&deps.storage, &(low - 1).to_be_bytes())?;
+
+    let total_tokens = total_tokens_at(&deps.storage, Some(time))?;
+    let total_shares = TotalShares::load(&deps.storage)?.0.u128();
+
+    let elapsed = time.saturating_sub(snapshot.day);
+    if elapsed == 0 || snapshot.total_tokens == Uint128::zero() || total_shares == 0 {
+        return Err(ContractError::NotEnoughRateHistory.into());
+    }
+
+    // Growth of tokens per share, a lower price shows as no yield
+    let now = u256::from(total_tokens) * u256::from(snapshot.total_shares.u128());
+    let then = u256::from(snapshot.total_tokens.u128()) * u256::from(total_shares);
+    let growth = if now > then { now - then } else { u256::ZERO };
+
+    let apr = growth
+        .checked_mul(u256::from(APR_PRECISION) * u256::from(YEAR))
+        .ok_or_else(|| StdError::from(ContractError::Overflow("APR calculation overflow")))?
+        / (then * u256::from(elapsed));
+
+    to_binary(&QueryAnswer::EstimatedApr {
+        apr: Uint128(apr.as_u128()),
+        since: snapshot.day,
+    })
+}
+
+pub fn rounding_dust<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let dust = RoundingDust::load(&deps.storage)?;
+
+    to_binary(&QueryAnswer::RoundingDust {
+        tokens: dust.tokens,
+        remainder: dust.remainder,
+    })
+}
+
+pub fn instant_liquidity<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    to_binary(&QueryAnswer::InstantLiquidity {
+        amount: available_liquidity(&deps.storage)?,
+    })
+}
+
 pub fn unfunded<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     start: u64,
@@ -42,14 +249,18 @@ pub fn unfunded<S: Storage, A: Api, Q: Querier>(
 ) -> StdResult<Binary> {
     let mut total_bonded = Uint128::zero();
 
-    let queue = DailyUnbondingQueue::load(&deps.storage)?.0;
+    // Include the epoch the start falls in
+    let start = UnbondingEpochs::load(&deps.storage)?.round(start);
 
     let mut count = 0;
-    for item in queue.0.iter() {
-        if item.release >= start {
+    let mut epoch = None;
+    while let Some(next) = next_unfunded_day(&deps.storage, epoch)? {
+        epoch = Some(next);
+        if next >= start {
             if count >= total {
                 break;
             }
+            let item = daily_unbonding(&deps.storage, next)?;
             total_bonded += (item.unbonding - item.funded)?;
             count += 1;
         }
@@ -60,12 +271,86 @@ pub fn unfunded<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+// Most epochs listed in one schedule page
+const MAX_SCHEDULE_LIMIT: u32 = 100;
+
+///
+/// Lists a page of unfunded epochs and totals what the treasury still owes for them
+///
+pub fn unbonding_schedule<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<u64>,
+    limit: u32,
+    time: u64,
+) -> StdResult<Binary> {
+    let mut entries = vec![];
+    let mut overdue = Uint128::zero();
+    let mut upcoming = Uint128::zero();
+
+    // The next page starts after the last listed release
+    let mut day = match start_after {
+        Some(start_after) => unfunded_day_after(&deps.storage, start_after)?,
+        None => next_unfunded_day(&deps.storage, None)?,
+    };
+    while let Some(epoch) = day {
+        if entries.len() >= limit.min(MAX_SCHEDULE_LIMIT) as usize {
+            break;
+        }
+
+        let item = daily_unbonding(&deps.storage, epoch)?;
+        let remaining = (item.unbonding - item.funded)?;
+        if epoch <= time {
+            overdue += remaining;
+        } else {
+            upcoming += remaining;
+        }
+
+        entries.push(ScheduledUnbonding {
+            release: epoch,
+            unbonding: item.unbonding,
+            funded: item.funded,
+            remaining,
+        });
+        day = next_unfunded_day(&deps.storage, Some(epoch))?;
+    }
+
+    to_binary(&QueryAnswer::UnbondingSchedule {
+        entries,
+        overdue,
+        upcoming,
+    })
+}
+
 pub fn unbonding<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
     to_binary(&QueryAnswer::Unbonding {
         total: TotalUnbonding::load(&deps.storage)?.0,
     })
 }
 
+///
+/// Token balance including the rewards held by the account's shares
+///
+pub fn balance_with_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    account: &HumanAddr,
+    time: Option<u64>,
+) -> StdResult<Binary> {
+    let shares = UserShares::may_load(&deps.storage, account.as_str().as_bytes())?
+        .map_or(0, |shares| shares.0.u128());
+
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let total_tokens = total_tokens_at(&deps.storage, time)?;
+    let total_shares = TotalShares::load(&deps.storage)?.0.u128();
+
+    // Bonus shares from locks are not owned by the account
+    let amount = tokens_per_share(&stake_config, &shares, &total_tokens, &total_shares)?
+        .saturating_sub(locked_bonus(&deps.storage, account)?);
+
+    to_binary(&QueryAnswer::Balance {
+        amount: Uint128(amount),
+    })
+}
+
 pub fn staked<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     account: HumanAddr,
@@ -74,45 +359,216 @@ pub fn staked<S: Storage, A: Api, Q: Querier>(
     let tokens = ReadonlyBalances::from_storage(&deps.storage)
         .account_amount(&deps.api.canonical_address(&account)?);
 
-    let shares = UserShares::load(&deps.storage, account.as_str().as_bytes())?.0;
+    let owned_shares = UserShares::load(&deps.storage, account.as_str().as_bytes())?.0;
+
+    let stake_config = StakeConfig::load(&deps.storage)?;
+    let mut total_tokens = total_tokens_at(&deps.storage, time)?;
+    let mut total_shares = TotalShares::load(&deps.storage)?.0.u128();
+
+    // Expired locks are released on the next action of the account, show them as released
+    let (expired, locks): (Vec<Lock>, Vec<Lock>) = user_locks(&deps.storage, &account)?
+        .into_iter()
+        .partition(|lock| time.map_or(false, |time| lock.release <= time));
+    let expired_bonus: u128 = expired.iter().map(|lock| lock.bonus.u128()).sum();
+    let mut shares = owned_shares.u128();
+    if expired_bonus != 0 {
+        let burned = shares_per_token(&stake_config, &expired_bonus, &total_tokens, &total_shares)?
+            .min(shares);
+        shares -= burned;
+        total_shares -= burned;
+        total_tokens = total_tokens.saturating_sub(expired_bonus);
+    }
+    let bonus: u128 = locks.iter().map(|lock| lock.bonus.u128()).sum();
 
     let (rewards, _) = calculate_rewards(
-        &StakeConfig::load(&deps.storage)?,
-        tokens,
-        shares.u128(),
-        TotalTokens::load(&deps.storage)?.0.u128(),
-        TotalShares::load(&deps.storage)?.0.u128(),
+        &stake_config,
+        tokens + bonus,
+        shares,
+        total_tokens,
+        total_shares,
     )?;
 
+    // Reported losses are shown before they are realized
+    let tokens = tokens.min(
+        tokens_per_share(&stake_config, &shares, &total_tokens, &total_shares)?
+            .saturating_sub(bonus),
+    );
+
     let queue = UnbondingQueue::may_load(&deps.storage, account.as_str().as_bytes())?
         .unwrap_or_else(|| UnbondingQueue(VecQueue::new(vec![])));
 
     let mut unbonding = Uint128::zero();
     let mut unbonded = Uint128::zero();
+    let mut unbondings = vec![];
 
-    for item in queue.0 .0.iter() {
+    let ids = unbonding_ids(&deps.storage, &account, queue.0 .0.len())?;
+    for (item, id) in queue.0 .0.iter().zip(ids) {
+        let amount = unbonding_value(&deps.storage, item)?;
+        unbondings.push(UnbondingEntry {
+            id,
+            amount,
+            release: item.release,
+        });
         if let Some(time) = time {
             if item.release <= time {
-                unbonded += item.amount;
+                unbonded += amount;
             } else {
-                unbonding += item.amount;
+                unbonding += amount;
             }
         } else {
-            unbonding += item.amount;
+            unbonding += amount;
+        }
+    }
+
+    let mut vesting = Uint128::zero();
+    let mut vested = Uint128::zero();
+
+    for item in user_vesting(&deps.storage, &account)? {
+        let remaining = (item.amount - item.claimed)?;
+        if let Some(time) = time {
+            let claimable = Uint128(item.claimable(time));
+            vested += claimable;
+            vesting += (remaining - claimable)?;
+        } else {
+            vesting += remaining;
         }
     }
 
     to_binary(&QueryAnswer::Staked {
         tokens: Uint128(tokens),
-        shares,
+        shares: Uint128(shares),
         pending_rewards: Uint128(rewards),
         unbonding,
         unbonded: time.map(|_| unbonded),
+        unbondings,
         cooldown: UserCooldown::may_load(&deps.storage, account.as_str().as_bytes())?
             .unwrap_or(UserCooldown {
                 total: Default::default(),
                 queue: VecQueue(vec![]),
             })
             .queue,
+        reward_tokens: pending_reward_tokens(&deps.storage, &account, owned_shares.u128())?,
+        auto_compound: is_auto_compounding(&deps.storage, &account)?,
+        locks,
+        vesting,
+        vested: time.map(|_| vested),
+    })
+}
+
+pub fn operator<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    owner: HumanAddr,
+    operator: HumanAddr,
+) -> StdResult<Binary> {
+    let approval = read_operator(
+        &deps.storage,
+        &deps.api.canonical_address(&owner)?,
+        &deps.api.canonical_address(&operator)?,
+    )?;
+
+    to_binary(&QueryAnswer::Operator {
+        owner,
+        operator,
+        approval,
+    })
+}
+
+///
+/// Walks a page of holders and reports per-user state that does not fit the global state,
+/// the running sums are compared with the totals on the page holding the last holder
+///
+pub fn audit_invariants<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+    page: u32,
+    page_size: u32,
+    totals: Option<AuditTotals>,
+) -> StdResult<Binary> {
+    let config = ReadonlyConfig::from_storage(&deps.storage);
+    if address != config.constants()?.admin {
+        return Err(ContractError::NotAdmin.into());
+    }
+
+    let total_supply = config.total_supply();
+    let total_shares = TotalShares::load(&deps.storage)?.0;
+    let total_unbonding = TotalUnbonding::load(&deps.storage)?.0;
+    let holders = HolderCount::load(&deps.storage)?.0;
+
+    let mut totals = totals.unwrap_or_default();
+    let mut mismatches = vec![];
+
+    // Pages past the last holder are empty
+    let start = holders.min(page as u64 * page_size as u64);
+    let end = holders.min(start + page_size as u64);
+
+    let balances = ReadonlyBalances::from_storage(&deps.storage);
+    for index in start..end {
+        let account = Holder::load(&deps.storage, &index.to_be_bytes())?.0;
+
+        let tokens = balances.account_amount(&deps.api.canonical_address(&account)?);
+        let shares = UserShares::may_load(&deps.storage, account.as_str().as_bytes())?
+            .map_or(Uint128::zero(), |shares| shares.0);
+
+        let queue = UnbondingQueue::may_load(&deps.storage, account.as_str().as_bytes())?
+            .unwrap_or_else(|| UnbondingQueue(VecQueue::new(vec![])));
+        let mut unbonding = Uint128::zero();
+        for item in queue.0 .0.iter() {
+            unbonding += unbonding_value(&deps.storage, item)?;
+        }
+
+        if shares > total_shares {
+            mismatches.push(format!(
+                "{} has {} shares but the total is {}",
+                account, shares, total_shares
+            ));
+        }
+        if tokens != 0 && shares == Uint128::zero() {
+            mismatches.push(format!("{} has {} tokens but no shares", account, tokens));
+        }
+        if unbonding > total_unbonding {
+            mismatches.push(format!(
+                "{} is unbonding {} but the total is {}",
+                account, unbonding, total_unbonding
+            ));
+        }
+
+        totals.shares += shares;
+        totals.tokens += Uint128(tokens);
+        totals.unbonding += unbonding;
+        totals.unbonding_entries += queue.0 .0.len() as u64;
+    }
+
+    // Pages past the end hold no holders and are not compared again
+    if end == holders && (start < end || page == 0) {
+        if totals.shares != total_shares {
+            mismatches.push(format!(
+                "Holder shares add up to {} but the total is {}",
+                totals.shares, total_shares
+            ));
+        }
+        if totals.tokens.u128() != total_supply {
+            mismatches.push(format!(
+                "Holder balances add up to {} but the total supply is {}",
+                totals.tokens, total_supply
+            ));
+        }
+        // Each unbonding can be rounded down once by reported losses
+        let difference = total_unbonding.u128().max(totals.unbonding.u128())
+            - total_unbonding.u128().min(totals.unbonding.u128());
+        if difference > totals.unbonding_entries as u128 {
+            mismatches.push(format!(
+                "Holder unbondings add up to {} but the total is {}",
+                totals.unbonding, total_unbonding
+            ));
+        }
+    }
+
+    to_binary(&QueryAnswer::AuditInvariants {
+        totals,
+        total_shares,
+        total_supply: Uint128(total_supply),
+        total_unbonding,
+        holders,
+        mismatches,
     })
 }
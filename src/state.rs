@@ -30,6 +30,7 @@ pub const PREFIX_BALANCES: &[u8] = b"balances";
 pub const PREFIX_ALLOWANCES: &[u8] = b"allowances";
 pub const PREFIX_VIEW_KEY: &[u8] = b"viewingkey";
 pub const PREFIX_RECEIVERS: &[u8] = b"receivers";
+pub const PREFIX_OPERATORS: &[u8] = b"operators";
 
 // Config
 
@@ -42,6 +43,8 @@ pub struct Constants {
     pub prng_seed: Vec<u8>,
     // privacy configuration
     pub total_supply_is_public: bool,
+    // whether balance queries include pending rewards
+    pub balance_with_rewards: bool,
     // the address of this contract, used to validate query permits
     pub contract_address: HumanAddr,
 }
@@ -334,6 +337,52 @@ pub fn write_allowance<S: Storage>(
     owner_store.store(spender.as_slice(), &allowance)
 }
 
+// Operators
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Default, JsonSchema)]
+pub struct Operator {
+    // Basis points of the claimed rewards the operator can keep as a tip
+    pub max_tip: u64,
+    pub expiration: Option<u64>,
+}
+
+impl Operator {
+    pub fn is_expired_at(&self, block: &cosmwasm_std::BlockInfo) -> bool {
+        match self.expiration {
+            Some(time) => block.time >= time,
+            None => false, // approval has no expiration
+        }
+    }
+}
+
+pub fn read_operator<S: Storage>(
+    store: &S,
+    owner: &CanonicalAddr,
+    operator: &CanonicalAddr,
+) -> StdResult<Option<Operator>> {
+    let owner_store =
+        ReadonlyPrefixedStorage::multilevel(&[PREFIX_OPERATORS, owner.as_slice()], store);
+    let owner_store = TypedStore::attach(&owner_store);
+    owner_store.may_load(operator.as_slice())
+}
+
+pub fn write_operator<S: Storage>(
+    store: &mut S,
+    owner: &CanonicalAddr,
+    operator: &CanonicalAddr,
+    approval: Operator,
+) -> StdResult<()> {
+    let mut owner_store = PrefixedStorage::multilevel(&[PREFIX_OPERATORS, owner.as_slice()], store);
+    let mut owner_store = TypedStoreMut::attach(&mut owner_store);
+
+    owner_store.store(operator.as_slice(), &approval)
+}
+
+pub fn remove_operator<S: Storage>(store: &mut S, owner: &CanonicalAddr, operator: &CanonicalAddr) {
+    let mut owner_store = PrefixedStorage::multilevel(&[PREFIX_OPERATORS, owner.as_slice()], store);
+    owner_store.remove(operator.as_slice())
+}
+
 // Viewing Keys
 
 pub fn write_viewing_key<S: Storage>(store: &mut S, owner: &CanonicalAddr, key: &ViewingKey) {
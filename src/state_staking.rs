@@ -1,7 +1,9 @@
 use cosmwasm_std::{HumanAddr, Uint128};
+use ethnum::u256;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use shade_protocol::shd_staking::stake::{Cooldown, DailyUnbonding, Unbonding, VecQueue};
+use shade_protocol::utils::asset::Contract;
 use shade_protocol::utils::storage::{BucketStorage, SingletonStorage};
 
 // used to determine what each token is worth to calculate rewards
@@ -47,6 +49,245 @@ impl SingletonStorage for TotalUnbonding {
     const NAMESPACE: &'static [u8] = b"total_unbonding";
 }
 
+// Seconds over which received rewards are released into the total tokens
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RewardDripPeriod(pub u64);
+
+impl SingletonStorage for RewardDripPeriod {
+    const NAMESPACE: &'static [u8] = b"reward_drip_period";
+}
+
+// Rewards waiting to be released into the total tokens
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RewardDrip {
+    pub remaining: Uint128,
+    pub last_update: u64,
+    pub end: u64,
+}
+
+impl SingletonStorage for RewardDrip {
+    const NAMESPACE: &'static [u8] = b"reward_drip";
+}
+
+impl RewardDrip {
+    // Amount that should have been released by the given time
+    pub fn released(&self, time: u64) -> u128 {
+        if time >= self.end {
+            return self.remaining.u128();
+        }
+        if time <= self.last_update {
+            return 0;
+        }
+
+        (u256::from(self.remaining.u128()) * u256::from(time - self.last_update)
+            / u256::from(self.end - self.last_update))
+        .as_u128()
+    }
+
+    // Tokens released per second
+    pub fn rate(&self, time: u64) -> Uint128 {
+        if time >= self.end {
+            return Uint128::zero();
+        }
+
+        let start = self.last_update.max(time);
+        Uint128((self.remaining.u128() - self.released(time)) / (self.end - start) as u128)
+    }
+}
+
+// Stake rate history
+
+// block time of the last share price change
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LastRateChange(pub u64);
+
+impl SingletonStorage for LastRateChange {
+    const NAMESPACE: &'static [u8] = b"last_rate_change";
+}
+
+// share price when the first state change of a day happened, keyed by its index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RateSnapshot {
+    pub day: u64,
+    pub total_tokens: Uint128,
+    pub total_shares: Uint128,
+}
+
+impl BucketStorage for RateSnapshot {
+    const NAMESPACE: &'static [u8] = b"rate_snapshot";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RateSnapshotCount(pub u64);
+
+impl SingletonStorage for RateSnapshotCount {
+    const NAMESPACE: &'static [u8] = b"rate_snapshot_count";
+}
+
+// Holders
+
+// every account that ever held staked tokens, keyed by its index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Holder(pub HumanAddr);
+
+impl BucketStorage for Holder {
+    const NAMESPACE: &'static [u8] = b"holder";
+}
+
+// index of an account in the holder registry
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HolderIndex(pub u64);
+
+impl BucketStorage for HolderIndex {
+    const NAMESPACE: &'static [u8] = b"holder_index";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HolderCount(pub u64);
+
+impl SingletonStorage for HolderCount {
+    const NAMESPACE: &'static [u8] = b"holder_count";
+}
+
+// Reward vesting
+
+// Seconds over which claimed rewards unlock, zero pays them out right away
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RewardVestingPeriod(pub u64);
+
+impl SingletonStorage for RewardVestingPeriod {
+    const NAMESPACE: &'static [u8] = b"reward_vesting_period";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Vesting {
+    pub amount: Uint128,
+    pub claimed: Uint128,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Vesting {
+    // Amount unlocked by the given time, including what was already claimed
+    pub fn vested(&self, time: u64) -> u128 {
+        if time >= self.end {
+            return self.amount.u128();
+        }
+        if time <= self.start {
+            return 0;
+        }
+
+        (u256::from(self.amount.u128()) * u256::from(time - self.start)
+            / u256::from(self.end - self.start))
+        .as_u128()
+    }
+
+    // Unlocked amount that can be claimed
+    pub fn claimable(&self, time: u64) -> u128 {
+        self.vested(time) - self.claimed.u128()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UserVesting(pub Vec<Vesting>);
+
+impl BucketStorage for UserVesting {
+    const NAMESPACE: &'static [u8] = b"user_vesting";
+}
+
+// tokens truncated away from reward claims
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RoundingDust {
+    // whole tokens already taken out of the total tokens
+    pub tokens: Uint128,
+    // fraction of a token not yet taken out, scaled by DUST_PRECISION
+    pub remainder: Uint128,
+}
+
+impl SingletonStorage for RoundingDust {
+    const NAMESPACE: &'static [u8] = b"rounding_dust";
+}
+
+// Limits on bonded tokens, these are checked against the tokens bonded and not their rewards
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BondLimits {
+    pub min_bond: Option<Uint128>,
+    pub max_bond_per_account: Option<Uint128>,
+    pub max_total_staked: Option<Uint128>,
+}
+
+impl SingletonStorage for BondLimits {
+    const NAMESPACE: &'static [u8] = b"bond_limits";
+}
+
+// Fees kept by the treasury, in basis points
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StakeFees {
+    pub bond: u64,
+    pub unbond: u64,
+}
+
+impl SingletonStorage for StakeFees {
+    const NAMESPACE: &'static [u8] = b"stake_fees";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct InstantUnbondConfig {
+    // Taken from instant unbonds and given back to the stakers, in basis points
+    pub penalty: u64,
+    // Newly staked tokens are kept back until the buffer reaches this size
+    pub buffer: Uint128,
+}
+
+impl SingletonStorage for InstantUnbondConfig {
+    const NAMESPACE: &'static [u8] = b"instant_unbond_config";
+}
+
+// Treasury notifications of bonds and unbonds, only sent while the code hash is set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TreasuryCallback {
+    // Must match the contract the treasury address points to
+    pub code_hash: Option<String>,
+}
+
+impl SingletonStorage for TreasuryCallback {
+    const NAMESPACE: &'static [u8] = b"treasury_callback";
+}
+
+// staked tokens held by the contract to pay out instant unbonds
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LiquidityBuffer(pub Uint128);
+
+impl SingletonStorage for LiquidityBuffer {
+    const NAMESPACE: &'static [u8] = b"liquidity_buffer";
+}
+
+// staked token rewards held by the contract until they are claimed, dripping ones included
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HeldRewards(pub Uint128);
+
+impl SingletonStorage for HeldRewards {
+    const NAMESPACE: &'static [u8] = b"held_rewards";
+}
+
 // Distributors wrappers
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -65,6 +306,60 @@ impl SingletonStorage for DistributorsEnabled {
     const NAMESPACE: &'static [u8] = b"distributors_transfer";
 }
 
+// Restakes implicit reward claims instead of sending them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AutoCompound(pub bool);
+
+impl BucketStorage for AutoCompound {
+    const NAMESPACE: &'static [u8] = b"auto_compound";
+}
+
+// Lock tiers
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LockTier {
+    // Seconds the stake stays locked
+    pub duration: u64,
+    // Extra shares given on top of the locked stake, in basis points
+    pub bonus: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LockTiers(pub Vec<LockTier>);
+
+impl SingletonStorage for LockTiers {
+    const NAMESPACE: &'static [u8] = b"lock_tiers";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Lock {
+    pub amount: Uint128,
+    // Tokens backing the bonus shares, these are not claimable and are removed on release
+    pub bonus: Uint128,
+    pub release: u64,
+}
+
+// tokens backing every bonus share, these are counted in the total tokens but never held
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TotalLockBonus(pub Uint128);
+
+impl SingletonStorage for TotalLockBonus {
+    const NAMESPACE: &'static [u8] = b"total_lock_bonus";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UserLocks(pub Vec<Lock>);
+
+impl BucketStorage for UserLocks {
+    const NAMESPACE: &'static [u8] = b"user_locks";
+}
+
 // Unbonding Queues
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -75,12 +370,123 @@ impl BucketStorage for UnbondingQueue {
     const NAMESPACE: &'static [u8] = b"unbonding_queue";
 }
 
+// Unbondings released in an epoch and how much of them the treasury sent back, keyed by its start
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub struct DailyUnbondingQueue(pub VecQueue<DailyUnbonding>);
+pub struct DailyUnbondingRecord(pub DailyUnbonding);
 
-impl SingletonStorage for DailyUnbondingQueue {
-    const NAMESPACE: &'static [u8] = b"daily_unbonding_queue";
+impl BucketStorage for DailyUnbondingRecord {
+    const NAMESPACE: &'static [u8] = b"daily_unbonding";
+}
+
+// Length of the epochs unbondings are funded in, from the release time it applies to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnbondingEpoch {
+    pub start: u64,
+    pub length: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnbondingEpochs(pub Vec<UnbondingEpoch>);
+
+impl SingletonStorage for UnbondingEpochs {
+    const NAMESPACE: &'static [u8] = b"unbonding_epochs";
+}
+
+impl UnbondingEpochs {
+    // Start of the epoch the release time falls in
+    pub fn round(&self, release: u64) -> u64 {
+        match self.0.iter().rev().find(|epoch| epoch.start <= release) {
+            Some(epoch) => release - (release - epoch.start) % epoch.length,
+            None => release,
+        }
+    }
+}
+
+// Latest release time of any unbonding
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LastUnbondingRelease(pub u64);
+
+impl SingletonStorage for LastUnbondingRelease {
+    const NAMESPACE: &'static [u8] = b"last_unbonding_release";
+}
+
+// Oldest and newest epochs that are not fully funded yet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnfundedDays {
+    pub head: Option<u64>,
+    pub tail: Option<u64>,
+}
+
+impl SingletonStorage for UnfundedDays {
+    const NAMESPACE: &'static [u8] = b"unfunded_days_cursor";
+}
+
+// keyed by epoch start, neighbours of an epoch that is not fully funded yet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnfundedDay {
+    pub prev: Option<u64>,
+    pub next: Option<u64>,
+}
+
+impl BucketStorage for UnfundedDay {
+    const NAMESPACE: &'static [u8] = b"unfunded_day";
+}
+
+// IDs of an account's queued unbondings, one per queue entry in the same order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnbondingIds(pub Vec<u64>);
+
+impl BucketStorage for UnbondingIds {
+    const NAMESPACE: &'static [u8] = b"unbonding_ids";
+}
+
+// Next unbonding ID to hand out
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnbondingCount(pub u64);
+
+impl SingletonStorage for UnbondingCount {
+    const NAMESPACE: &'static [u8] = b"unbonding_count";
+}
+
+// keyed by release day, portion of that day's unbondings left after reported losses
+// scaled by UNBONDING_LOSS_PRECISION
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnbondingLoss(pub Uint128);
+
+impl BucketStorage for UnbondingLoss {
+    const NAMESPACE: &'static [u8] = b"unbonding_loss";
+}
+
+// loss reported by the treasury and how it was split, keyed by its index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReportedLoss {
+    pub amount: Uint128,
+    pub staked: Uint128,
+    pub unbonding: Uint128,
+    pub time: u64,
+    pub memo: Option<String>,
+}
+
+impl BucketStorage for ReportedLoss {
+    const NAMESPACE: &'static [u8] = b"reported_loss";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReportedLossCount(pub u64);
+
+impl SingletonStorage for ReportedLossCount {
+    const NAMESPACE: &'static [u8] = b"reported_loss_count";
 }
 
 // Used for vote cooldown after send
@@ -126,3 +532,37 @@ impl UserCooldown {
         }
     }
 }
+
+// Reward tokens
+
+// whitelisted tokens that can be distributed as rewards besides the staked token
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RewardTokens(pub Vec<Contract>);
+
+impl SingletonStorage for RewardTokens {
+    const NAMESPACE: &'static [u8] = b"reward_tokens";
+}
+
+// accumulated rewards per share, scaled by REWARD_INDEX_PRECISION
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RewardIndex(pub Uint128);
+
+impl BucketStorage for RewardIndex {
+    const NAMESPACE: &'static [u8] = b"reward_index";
+}
+
+// keyed by reward token and user
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UserReward {
+    // last seen reward index
+    pub index: Uint128,
+    // rewards accrued but not yet claimed
+    pub pending: Uint128,
+}
+
+impl BucketStorage for UserReward {
+    const NAMESPACE: &'static [u8] = b"user_reward";
+}
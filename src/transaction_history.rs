@@ -64,9 +64,27 @@ pub enum TxAction {
     },
     ClaimUnbond {
         staker: HumanAddr,
+        // Set when the tokens were sent somewhere else than the staker
+        recipient: Option<HumanAddr>,
     },
     ClaimReward {
         staker: HumanAddr,
+        recipient: Option<HumanAddr>,
+    },
+    Compound {
+        staker: HumanAddr,
+    },
+    ReportLoss {
+        reporter: HumanAddr,
+    },
+    Fee {
+        payer: HumanAddr,
+    },
+    Lock {
+        staker: HumanAddr,
+    },
+    Unlock {
+        staker: HumanAddr,
     },
 }
 
@@ -132,6 +150,11 @@ enum TxCode {
     Unbond = 8,
     ClaimUnbond = 9,
     ClaimReward = 10,
+    Compound = 11,
+    ReportLoss = 12,
+    Fee = 13,
+    Lock = 14,
+    Unlock = 15,
 }
 
 impl TxCode {
@@ -153,6 +176,11 @@ impl TxCode {
             8 => Ok(Unbond),
             9 => Ok(ClaimUnbond),
             10 => Ok(ClaimReward),
+            11 => Ok(Compound),
+            12 => Ok(ReportLoss),
+            13 => Ok(Fee),
+            14 => Ok(Lock),
+            15 => Ok(Unlock),
             other => Err(StdError::generic_err(format!(
                 "Unexpected Tx code in transaction history: {} Storage is corrupted.",
                 other
@@ -235,18 +263,58 @@ impl StoredTxAction {
             address3: None,
         }
     }
-    fn claim_unbond(staker: CanonicalAddr) -> Self {
+    fn claim_unbond(staker: CanonicalAddr, recipient: Option<CanonicalAddr>) -> Self {
         Self {
             tx_type: TxCode::ClaimUnbond.to_u8(),
             address1: Some(staker),
-            address2: None,
+            address2: recipient,
             address3: None,
         }
     }
-    fn claim_reward(staker: CanonicalAddr) -> Self {
+    fn claim_reward(staker: CanonicalAddr, recipient: Option<CanonicalAddr>) -> Self {
         Self {
             tx_type: TxCode::ClaimReward.to_u8(),
             address1: Some(staker),
+            address2: recipient,
+            address3: None,
+        }
+    }
+    fn compound(staker: CanonicalAddr) -> Self {
+        Self {
+            tx_type: TxCode::Compound.to_u8(),
+            address1: Some(staker),
+            address2: None,
+            address3: None,
+        }
+    }
+    fn report_loss(reporter: CanonicalAddr) -> Self {
+        Self {
+            tx_type: TxCode::ReportLoss.to_u8(),
+            address1: Some(reporter),
+            address2: None,
+            address3: None,
+        }
+    }
+    fn fee(payer: CanonicalAddr) -> Self {
+        Self {
+            tx_type: TxCode::Fee.to_u8(),
+            address1: Some(payer),
+            address2: None,
+            address3: None,
+        }
+    }
+    fn lock(staker: CanonicalAddr) -> Self {
+        Self {
+            tx_type: TxCode::Lock.to_u8(),
+            address1: Some(staker),
+            address2: None,
+            address3: None,
+        }
+    }
+    fn unlock(staker: CanonicalAddr) -> Self {
+        Self {
+            tx_type: TxCode::Unlock.to_u8(),
+            address1: Some(staker),
             address2: None,
             address3: None,
         }
@@ -322,12 +390,45 @@ impl StoredTxAction {
             TxCode::ClaimUnbond => {
                 let staker = self.address1.ok_or_else(staker_addr_err)?;
                 let staker = api.human_address(&staker)?;
-                TxAction::ClaimUnbond { staker }
+                let recipient = self
+                    .address2
+                    .map(|recipient| api.human_address(&recipient))
+                    .transpose()?;
+                TxAction::ClaimUnbond { staker, recipient }
             }
             TxCode::ClaimReward => {
                 let staker = self.address1.ok_or_else(staker_addr_err)?;
                 let staker = api.human_address(&staker)?;
-                TxAction::ClaimReward { staker }
+                let recipient = self
+                    .address2
+                    .map(|recipient| api.human_address(&recipient))
+                    .transpose()?;
+                TxAction::ClaimReward { staker, recipient }
+            }
+            TxCode::Compound => {
+                let staker = self.address1.ok_or_else(staker_addr_err)?;
+                let staker = api.human_address(&staker)?;
+                TxAction::Compound { staker }
+            }
+            TxCode::ReportLoss => {
+                let reporter = self.address1.ok_or_else(staker_addr_err)?;
+                let reporter = api.human_address(&reporter)?;
+                TxAction::ReportLoss { reporter }
+            }
+            TxCode::Fee => {
+                let payer = self.address1.ok_or_else(staker_addr_err)?;
+                let payer = api.human_address(&payer)?;
+                TxAction::Fee { payer }
+            }
+            TxCode::Lock => {
+                let staker = self.address1.ok_or_else(staker_addr_err)?;
+                let staker = api.human_address(&staker)?;
+                TxAction::Lock { staker }
+            }
+            TxCode::Unlock => {
+                let staker = self.address1.ok_or_else(staker_addr_err)?;
+                let staker = api.human_address(&staker)?;
+                TxAction::Unlock { staker }
             }
         };
 
@@ -561,6 +662,7 @@ pub fn store_unbond<S: Storage>(
 pub fn store_claim_unbond<S: Storage>(
     store: &mut S,
     staker: &CanonicalAddr,
+    recipient: Option<CanonicalAddr>,
     amount: Uint128,
     denom: String,
     memo: Option<String>,
@@ -568,7 +670,7 @@ pub fn store_claim_unbond<S: Storage>(
 ) -> StdResult<()> {
     let id = increment_tx_count(store)?;
     let coins = Coin { denom, amount };
-    let action = StoredTxAction::claim_unbond(staker.clone());
+    let action = StoredTxAction::claim_unbond(staker.clone(), recipient);
     let tx = StoredRichTx::new(id, action, coins, memo, block);
 
     append_tx(store, &tx, staker)?;
@@ -577,6 +679,97 @@ pub fn store_claim_unbond<S: Storage>(
 }
 
 pub fn store_claim_reward<S: Storage>(
+    store: &mut S,
+    staker: &CanonicalAddr,
+    recipient: Option<CanonicalAddr>,
+    amount: Uint128,
+    denom: String,
+    memo: Option<String>,
+    block: &cosmwasm_std::BlockInfo,
+) -> StdResult<()> {
+    let id = increment_tx_count(store)?;
+    let coins = Coin { denom, amount };
+    let action = StoredTxAction::claim_reward(staker.clone(), recipient);
+    let tx = StoredRichTx::new(id, action, coins, memo, block);
+
+    append_tx(store, &tx, staker)?;
+
+    Ok(())
+}
+
+pub fn store_compound<S: Storage>(
+    store: &mut S,
+    staker: &CanonicalAddr,
+    amount: Uint128,
+    denom: String,
+    memo: Option<String>,
+    block: &cosmwasm_std::BlockInfo,
+) -> StdResult<()> {
+    let id = increment_tx_count(store)?;
+    let coins = Coin { denom, amount };
+    let action = StoredTxAction::compound(staker.clone());
+    let tx = StoredRichTx::new(id, action, coins, memo, block);
+
+    append_tx(store, &tx, staker)?;
+
+    Ok(())
+}
+
+pub fn store_report_loss<S: Storage>(
+    store: &mut S,
+    reporter: &CanonicalAddr,
+    amount: Uint128,
+    denom: String,
+    memo: Option<String>,
+    block: &cosmwasm_std::BlockInfo,
+) -> StdResult<()> {
+    let id = increment_tx_count(store)?;
+    let coins = Coin { denom, amount };
+    let action = StoredTxAction::report_loss(reporter.clone());
+    let tx = StoredRichTx::new(id, action, coins, memo, block);
+
+    append_tx(store, &tx, reporter)?;
+
+    Ok(())
+}
+
+pub fn store_fee<S: Storage>(
+    store: &mut S,
+    payer: &CanonicalAddr,
+    amount: Uint128,
+    denom: String,
+    memo: Option<String>,
+    block: &cosmwasm_std::BlockInfo,
+) -> StdResult<()> {
+    let id = increment_tx_count(store)?;
+    let coins = Coin { denom, amount };
+    let action = StoredTxAction::fee(payer.clone());
+    let tx = StoredRichTx::new(id, action, coins, memo, block);
+
+    append_tx(store, &tx, payer)?;
+
+    Ok(())
+}
+
+pub fn store_lock<S: Storage>(
+    store: &mut S,
+    staker: &CanonicalAddr,
+    amount: Uint128,
+    denom: String,
+    memo: Option<String>,
+    block: &cosmwasm_std::BlockInfo,
+) -> StdResult<()> {
+    let id = increment_tx_count(store)?;
+    let coins = Coin { denom, amount };
+    let action = StoredTxAction::lock(staker.clone());
+    let tx = StoredRichTx::new(id, action, coins, memo, block);
+
+    append_tx(store, &tx, staker)?;
+
+    Ok(())
+}
+
+pub fn store_unlock<S: Storage>(
     store: &mut S,
     staker: &CanonicalAddr,
     amount: Uint128,
@@ -586,7 +779,7 @@ pub fn store_claim_reward<S: Storage>(
 ) -> StdResult<()> {
     let id = increment_tx_count(store)?;
     let coins = Coin { denom, amount };
-    let action = StoredTxAction::claim_reward(staker.clone());
+    let action = StoredTxAction::unlock(staker.clone());
     let tx = StoredRichTx::new(id, action, coins, memo, block);
 
     append_tx(store, &tx, staker)?;
@@ -0,0 +1,45 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::state_staking::TreasuryCallback;
+use cosmwasm_std::{CosmosMsg, StdResult, Storage, Uint128};
+use secret_toolkit::utils::HandleCallback;
+use shade_protocol::shd_staking::stake::StakeConfig;
+use shade_protocol::utils::storage::SingletonStorage;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TreasuryHandleMsg {
+    // Tokens were bonded into the pool
+    Bonded {
+        amount: Uint128,
+    },
+    // Tokens must be funded before the release epoch can be claimed
+    Unbonded {
+        amount: Uint128,
+        // Start of the epoch the unbonding is released in
+        release: u64,
+    },
+}
+
+impl HandleCallback for TreasuryHandleMsg {
+    const BLOCK_SIZE: usize = 256;
+}
+
+///
+/// Notifies the treasury if it set a callback code hash
+///
+pub fn notify_treasury<S: Storage>(
+    storage: &S,
+    messages: &mut Vec<CosmosMsg>,
+    stake_config: &StakeConfig,
+    msg: TreasuryHandleMsg,
+) -> StdResult<()> {
+    if let Some(treasury) = &stake_config.treasury {
+        if let Some(code_hash) = TreasuryCallback::load(storage)?.code_hash {
+            messages.push(msg.to_cosmos_msg(code_hash, treasury.clone(), None)?);
+        }
+    }
+
+    Ok(())
+}